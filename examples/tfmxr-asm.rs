@@ -0,0 +1,44 @@
+//! Assemble a module from source text, or turn a module into source text
+
+use {anyhow::Context, clap::Parser, std::path::PathBuf, tfmxr::Module};
+
+#[derive(Parser)]
+struct Args {
+    /// Source text, or .mdat data with `--disassemble`
+    input: PathBuf,
+    /// Where to write the .mdat data, or the source text. By default the .mdat data goes
+    /// next to the input, and the source text to stdout.
+    #[arg(short = 'o', long)]
+    output: Option<PathBuf>,
+    /// Turn .mdat data into source text
+    #[arg(short = 'd', long)]
+    disassemble: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Info)
+        .parse_env("RUST_LOG")
+        .init();
+    if args.disassemble {
+        let mdat = std::fs::read(&args.input).context("Failed to read .mdat data")?;
+        let source = Module::from_mdat(&mdat)
+            .context("Failed to load module")?
+            .to_source();
+        match args.output {
+            Some(path) => std::fs::write(path, source).context("Failed to write source")?,
+            None => print!("{source}"),
+        }
+        return Ok(());
+    }
+    let source = std::fs::read_to_string(&args.input).context("Failed to read source")?;
+    let module = Module::assemble(&source)
+        .with_context(|| format!("Failed to assemble {}", args.input.display()))?;
+    let output = args
+        .output
+        .unwrap_or_else(|| args.input.with_extension("mdat"));
+    std::fs::write(&output, module.to_mdat()).context("Failed to write .mdat data")?;
+    log::info!("Wrote {}", output.display());
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! Print the track table of every subsong of a module, and its patterns and macros
+
+use {
+    anyhow::Context,
+    clap::Parser,
+    tfmxr::{PlayerBuilder, TfmxPlayer},
+};
+
+#[derive(Parser)]
+struct Args {
+    mdat_path: String,
+    #[arg(short = 's', long)]
+    smpl_path: Option<String>,
+    /// Leave out the macros
+    #[arg(long)]
+    no_macros: bool,
+}
+
+fn print_song(player: &TfmxPlayer, idx: u8) {
+    println!("Song {idx:02}");
+    for step in player.disassemble_track(idx).unwrap_or_default() {
+        println!("  {step}");
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Info)
+        .parse_env("RUST_LOG")
+        .init();
+    let mut builder = PlayerBuilder::new(args.mdat_path);
+    if let Some(smpl) = args.smpl_path {
+        builder.smpl_file(smpl);
+    }
+    let player = builder.build().context("Failed to create player")?;
+    let info = player.module_info();
+    for row in &info.text {
+        println!("; {row}");
+    }
+    for song in &info.subsongs {
+        println!();
+        print_song(&player, song.index);
+    }
+    for num in 0..info.pattern_count {
+        println!();
+        println!("Pattern {num:02X}");
+        for step in player.disassemble_pattern(num as u8).unwrap_or_default() {
+            println!("  {step}");
+        }
+    }
+    if args.no_macros {
+        return Ok(());
+    }
+    for num in 0..info.macro_count {
+        println!();
+        println!("Macro {num:02X}");
+        for step in player.disassemble_macro(num as u8).unwrap_or_default() {
+            println!("  {step}");
+        }
+    }
+    Ok(())
+}
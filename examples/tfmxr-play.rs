@@ -20,6 +20,9 @@ struct Args {
     song: u8,
     #[arg(short = 'r', long, default_value = "44100")]
     sample_rate: u32,
+    /// How many times to play each song through, 0 to loop forever
+    #[arg(short = 'l', long, default_value = "1")]
+    loops: u32,
 }
 
 enum Msg {
@@ -44,6 +47,7 @@ fn main() {
         }
         let mut player = builder
             .starting_subsong(args.song)
+            .loop_count(args.loops)
             .sample_rate(args.sample_rate)
             .build()
             .context("Failed to create player")
@@ -107,6 +111,9 @@ fn main() {
                 'b' => {
                     send.send(Msg::Cmd(PlayerCmd::ToggleBlend)).unwrap();
                 }
+                'f' => {
+                    send.send(Msg::Cmd(PlayerCmd::ToggleLedFilter)).unwrap();
+                }
                 '1'..'9' => {
                     send.send(Msg::Cmd(PlayerCmd::ToggleCh(ch as u8 - b'1')))
                         .unwrap();
@@ -0,0 +1,88 @@
+//! Export every subsong of a module to WAV files or MIDI files, or its instruments
+
+use {
+    anyhow::Context,
+    clap::Parser,
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        path::PathBuf,
+        time::Duration,
+    },
+    tfmxr::{InstrumentFormat, PlayerBuilder, WavOptions},
+};
+
+#[derive(Parser)]
+struct Args {
+    mdat_path: String,
+    #[arg(short = 's', long)]
+    smpl_path: Option<String>,
+    /// Directory to write the WAV files to
+    #[arg(short = 'o', long, default_value = ".")]
+    out_dir: PathBuf,
+    #[arg(short = 'r', long, default_value = "44100")]
+    sample_rate: u32,
+    /// How many times to play each song through
+    #[arg(short = 'l', long, default_value = "1")]
+    loops: u32,
+    /// Seconds to fade out for after the last loop
+    #[arg(short = 'f', long, default_value = "0")]
+    fade: f32,
+    /// Write MIDI files of the notes instead of WAV files
+    #[arg(short = 'm', long)]
+    midi: bool,
+    /// Write the instruments instead of the songs
+    #[arg(short = 'i', long)]
+    instruments: bool,
+    /// Write the instruments as IFF 8SVX files instead of WAV files
+    #[arg(long = "8svx")]
+    iff_8svx: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Info)
+        .parse_env("RUST_LOG")
+        .init();
+    let mut builder = PlayerBuilder::new(args.mdat_path.clone());
+    if let Some(smpl) = args.smpl_path {
+        builder.smpl_file(smpl);
+    }
+    let player = builder
+        .sample_rate(args.sample_rate)
+        .build()
+        .context("Failed to create player")?;
+    let stem = PathBuf::from(&args.mdat_path)
+        .file_name()
+        .map_or_else(|| "song".into(), |name| name.to_string_lossy().into_owned());
+    if args.instruments {
+        let format = if args.iff_8svx {
+            InstrumentFormat::Iff8svx
+        } else {
+            InstrumentFormat::Wav
+        };
+        player.export_instruments(format, |idx, _| {
+            args.out_dir
+                .join(format!("{stem}-inst{idx:02}.{}", format.extension()))
+        })?;
+        return Ok(());
+    }
+    if args.midi {
+        for song in player.module_info().subsongs {
+            let path = args.out_dir.join(format!("{stem}-{:02}.mid", song.index));
+            log::info!("Writing song {} to {}", song.index, path.display());
+            let mut file = BufWriter::new(File::create(path)?);
+            player.write_midi(song.index, &mut file)?;
+            file.flush()?;
+        }
+        return Ok(());
+    }
+    player.export_wavs(
+        WavOptions::new()
+            .loop_count(args.loops)
+            .fade_out(Duration::from_secs_f32(args.fade)),
+        |idx| args.out_dir.join(format!("{stem}-{idx:02}.wav")),
+    )?;
+    Ok(())
+}
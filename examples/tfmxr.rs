@@ -14,6 +14,9 @@ struct Args {
     /// Song index
     #[arg(short = 't', long, default_value = "0")]
     song: u8,
+    /// How many times to play each song through, 0 to loop forever
+    #[arg(short = 'l', long, default_value = "1")]
+    loops: u32,
 }
 
 enum Msg {
@@ -38,6 +41,7 @@ fn main() {
         }
         let mut player = builder
             .starting_subsong(args.song)
+            .loop_count(args.loops)
             .build()
             .context("Failed to create player")
             .unwrap();
@@ -86,6 +90,9 @@ fn main() {
                 'b' => {
                     send.send(Msg::Cmd(PlayerCmd::ToggleBlend)).unwrap();
                 }
+                'f' => {
+                    send.send(Msg::Cmd(PlayerCmd::ToggleLedFilter)).unwrap();
+                }
                 '1'..'9' => {
                     send.send(Msg::Cmd(PlayerCmd::ToggleCh(ch as u8 - b'1')))
                         .unwrap();
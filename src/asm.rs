@@ -0,0 +1,899 @@
+use {
+    crate::{
+        TEXT_ROW_LEN,
+        disasm::{
+            MacroCommand, MacroStep, NOTE_NAMES, PatternCommand, PatternStep, TrackCommand,
+            TrackStep, TrackVoice,
+        },
+        module::{Macro, Module, Pattern, SongEntry},
+    },
+    std::{fmt, str::FromStr},
+    thiserror::Error,
+};
+
+/// Patterns and macros a module can have
+const MAX_ITEMS: usize = 128;
+
+/// Error when assembling a module, see [`Module::assemble`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Line {line}: {kind}")]
+pub struct AssembleError {
+    /// Line of the source the error is on, from 1
+    pub line: usize,
+    /// What went wrong
+    pub kind: AssembleErrorKind,
+}
+
+/// What went wrong when assembling a module
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AssembleErrorKind {
+    /// The line isn't a directive, and not in a section that takes steps
+    #[error("Step outside of the track table, a pattern or a macro")]
+    StepOutsideSection,
+    /// A word that isn't a directive or a command
+    #[error("Unknown command `{0}`")]
+    UnknownCommand(String),
+    /// An operand that isn't a number, or doesn't fit its field
+    #[error("Invalid operand `{0}`")]
+    InvalidOperand(String),
+    /// A command is missing an operand
+    #[error("Missing operand `{0}`")]
+    MissingOperand(&'static str),
+    /// A command has an operand it doesn't take
+    #[error("Unexpected operand `{0}`")]
+    UnexpectedOperand(String),
+    /// Patterns and macros are numbered in order, from 0
+    #[error("Expected number {0:02X}")]
+    OutOfOrder(usize),
+    /// The text has more than 6 rows, or a row is longer than 40 bytes
+    #[error("Text doesn't fit in the header")]
+    TextTooLong,
+    /// A string without quotes around it
+    #[error("Expected a string in quotes")]
+    ExpectedString,
+    /// A step or a song refers to a track step past the end of the track table
+    #[error("Track step {0} isn't defined")]
+    UndefinedTrackStep(u16),
+    /// A step refers to a pattern that isn't defined
+    #[error("Pattern {0:02X} isn't defined")]
+    UndefinedPattern(u8),
+    /// A step refers to a macro that isn't defined
+    #[error("Macro {0:02X} isn't defined")]
+    UndefinedMacro(u8),
+}
+
+use AssembleErrorKind as Kind;
+
+/// A number: decimal, or hex after `$` or `0x`, with an optional sign
+fn number<T: TryFrom<i64>>(text: &str) -> Result<T, Kind> {
+    let invalid = || Kind::InvalidOperand(text.to_owned());
+    let (negative, digits) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let value = digits
+        .strip_prefix('$')
+        .or_else(|| digits.strip_prefix("0x"))
+        .map_or_else(|| digits.parse(), |hex| i64::from_str_radix(hex, 16))
+        .map_err(|_| invalid())?;
+    T::try_from(if negative { -value } else { value }).map_err(|_| invalid())
+}
+
+/// A hex number, where the `$` is optional
+fn hex<T: TryFrom<i64>>(text: &str) -> Result<T, Kind> {
+    let digits = text.strip_prefix('$').unwrap_or(text);
+    i64::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| Kind::InvalidOperand(text.to_owned()))
+}
+
+/// Check that an address fits the 24 bits of a step
+fn address(addr: u32) -> Result<u32, Kind> {
+    if addr > 0xFF_FFFF {
+        return Err(Kind::InvalidOperand(format!("${addr:X}")));
+    }
+    Ok(addr)
+}
+
+/// A note name like `C#2`, where `C-0` is note 0.
+///
+/// Returns `None` if the text doesn't start like a note name.
+fn note(text: &str) -> Option<Result<u8, Kind>> {
+    let name = text.get(..2)?.to_ascii_uppercase();
+    let index = NOTE_NAMES.iter().position(|&note| note == name)?;
+    let note = text[2..]
+        .parse::<u8>()
+        .ok()
+        .and_then(|octave| octave.checked_mul(12))
+        .map(|note| note.saturating_add(index as u8))
+        .filter(|&note| note < 64);
+    Some(note.ok_or_else(|| Kind::InvalidOperand(text.to_owned())))
+}
+
+/// The operands of a command: positional ones, and `key=value` pairs
+struct Operands<'a> {
+    /// In reverse order
+    positional: Vec<&'a str>,
+    named: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Operands<'a> {
+    fn new(tokens: impl Iterator<Item = &'a str>) -> Self {
+        let mut operands = Self {
+            positional: Vec::new(),
+            named: Vec::new(),
+        };
+        for token in tokens {
+            match token.split_once('=') {
+                Some(pair) => operands.named.push(pair),
+                None => operands.positional.push(token),
+            }
+        }
+        operands.positional.reverse();
+        operands
+    }
+    fn next(&mut self, name: &'static str) -> Result<&'a str, Kind> {
+        self.positional.pop().ok_or(Kind::MissingOperand(name))
+    }
+    /// Whether the flag `name` is there, as a positional operand
+    fn flag(&mut self, name: &'static str) -> bool {
+        let found = self
+            .positional
+            .iter()
+            .position(|op| op.eq_ignore_ascii_case(name));
+        found.map(|i| self.positional.remove(i)).is_some()
+    }
+    fn peek(&self) -> Option<&'a str> {
+        self.positional.last().copied()
+    }
+    fn text(&mut self, key: &'static str) -> Option<&'a str> {
+        let i = self
+            .named
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(key))?;
+        Some(self.named.remove(i).1)
+    }
+    fn get_opt<T: TryFrom<i64>>(&mut self, key: &'static str) -> Result<Option<T>, Kind> {
+        self.text(key).map(number).transpose()
+    }
+    fn get<T: TryFrom<i64>>(&mut self, key: &'static str) -> Result<T, Kind> {
+        self.get_opt(key)?.ok_or(Kind::MissingOperand(key))
+    }
+    /// Return `value` if all operands were used
+    fn finish<T>(self, value: T) -> Result<T, Kind> {
+        if let Some(token) = self.peek() {
+            return Err(Kind::UnexpectedOperand(token.to_owned()));
+        }
+        if let Some((key, value)) = self.named.first() {
+            return Err(Kind::UnexpectedOperand(format!("{key}={value}")));
+        }
+        Ok(value)
+    }
+}
+
+impl FromStr for MacroCommand {
+    type Err = AssembleErrorKind;
+    /// Parse a command the way it displays
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut tokens = text.split_whitespace();
+        let name = tokens.next().unwrap_or_default();
+        let mut ops = Operands::new(tokens);
+        let command = match name.to_ascii_lowercase().as_str() {
+            "dmaoff+reset" => Self::DmaOff {
+                reset: true,
+                after_loop: ops.flag("after_loop"),
+                volume: ops.get_opt("volume")?.unwrap_or(0),
+                absolute: ops.flag("absolute"),
+            },
+            "dmaoff" => Self::DmaOff {
+                reset: false,
+                after_loop: ops.flag("after_loop"),
+                volume: 0,
+                absolute: false,
+            },
+            "dmaon" => Self::DmaOn {
+                effects: ops.get("effects")?,
+            },
+            "setbegin" => Self::SetBegin {
+                addr: address(number(ops.next("address")?)?)?,
+            },
+            "setlen" => Self::SetLen {
+                len: ops.get("len")?,
+            },
+            "wait" => Self::Wait {
+                ticks: ops.get("ticks")?,
+                once: ops.flag("once"),
+            },
+            "loop" => Self::Loop {
+                count: ops.get("count")?,
+                step: ops.get("step")?,
+            },
+            "cont" => Self::Cont {
+                macro_num: ops.get("macro")?,
+                step: ops.get("step")?,
+            },
+            "stop" => Self::Stop,
+            "addnote" => Self::AddNote {
+                note: ops.get("note")?,
+                detune: ops.get("detune")?,
+            },
+            "setnote" => Self::SetNote {
+                note: ops.get("note")?,
+                detune: ops.get("detune")?,
+            },
+            "reset" => Self::Reset,
+            "portamento" => Self::Portamento {
+                speed: ops.get("speed")?,
+                rate: ops.get("rate")?,
+            },
+            "vibrato" => Self::Vibrato {
+                speed: ops.get("speed")?,
+                depth: ops.get("depth")?,
+            },
+            "addvolume" => Self::AddVolume {
+                volume: ops.get("volume")?,
+                skip: ops.flag("skip"),
+            },
+            "setvolume" => Self::SetVolume {
+                volume: ops.get("volume")?,
+                skip: ops.flag("skip"),
+            },
+            "envelope" => Self::Envelope {
+                rate: ops.get("rate")?,
+                interval: ops.get("interval")?,
+                end_volume: ops.get("end")?,
+            },
+            "loopkeyup" => Self::LoopKeyUp {
+                count: ops.get("count")?,
+                step: ops.get("step")?,
+            },
+            "addbegin" => Self::AddBegin {
+                ticks: ops.get("ticks")?,
+                offset: ops.get("offset")?,
+            },
+            "addlen" => Self::AddLen {
+                offset: ops.get("offset")?,
+            },
+            "waitkeyup" => Self::WaitKeyUp {
+                ticks: ops.get("ticks")?,
+            },
+            "gosub" => Self::GoSub {
+                macro_num: ops.get("macro")?,
+                step: ops.get("step")?,
+            },
+            "return" => Self::Return,
+            "setperiod" => Self::SetPeriod {
+                period: ops.get("period")?,
+            },
+            "sampleloop" => Self::SampleLoop {
+                offset: ops.get("offset")?,
+            },
+            "oneshot" => Self::OneShot,
+            "waitondma" => Self::WaitOnDma {
+                loops: ops.get("loops")?,
+            },
+            "splitkey" => Self::SplitKey {
+                note: ops.get("note")?,
+                step: ops.get("step")?,
+            },
+            "splitvolume" => Self::SplitVolume {
+                volume: ops.get("volume")?,
+                step: ops.get("step")?,
+            },
+            "addprevnote" => Self::AddPrevNote {
+                note: ops.get("note")?,
+                detune: ops.get("detune")?,
+            },
+            "cue" => Self::Cue {
+                index: ops.get("index")?,
+                value: ops.get("value")?,
+            },
+            "playmacro" => Self::PlayMacro {
+                macro_num: ops.get("macro")?,
+                channel: ops.get("channel")?,
+                detune: ops.get("detune")?,
+            },
+            "setbegintemp" => Self::SetBeginTemp {
+                addr: address(number(ops.next("address")?)?)?,
+            },
+            "unknown" => {
+                let opcode = number(ops.next("opcode")?)?;
+                let [_, b1, b2, b3] = address(hex(ops.next("operand")?)?)?.to_be_bytes();
+                Self::Unknown {
+                    opcode,
+                    operand: [b1, b2, b3],
+                }
+            }
+            _ => return Err(Kind::UnknownCommand(name.to_owned())),
+        };
+        ops.finish(command)
+    }
+}
+
+impl FromStr for PatternCommand {
+    type Err = AssembleErrorKind;
+    /// Parse a command the way it displays
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut tokens = text.split_whitespace();
+        let name = tokens.next().unwrap_or_default();
+        let mut ops = Operands::new(tokens);
+        if let Some(note) = note(name) {
+            let note = note?;
+            let command = if ops
+                .peek()
+                .is_some_and(|op| op.eq_ignore_ascii_case("porta"))
+            {
+                ops.next("porta")?;
+                Self::Portamento {
+                    note,
+                    speed: ops.get("speed")?,
+                    channel: ops.get("ch")?,
+                    rate: ops.get("rate")?,
+                }
+            } else {
+                let macro_num = hex(ops.next("macro")?)?;
+                let volume_channel: u8 = hex(ops.next("volume and channel")?)?;
+                let (volume, channel) = (volume_channel >> 4, volume_channel & 0xF);
+                match ops.get_opt("wait")? {
+                    Some(wait) => Self::NoteWait {
+                        note,
+                        macro_num,
+                        volume,
+                        channel,
+                        wait,
+                    },
+                    None => Self::Note {
+                        note,
+                        macro_num,
+                        volume,
+                        channel,
+                        detune: hex(ops.next("detune")?)?,
+                    },
+                }
+            };
+            return ops.finish(command);
+        }
+        let command = match name.to_ascii_lowercase().as_str() {
+            "end" => Self::End,
+            "loop" => Self::Loop {
+                count: ops.get("count")?,
+                step: ops.get("step")?,
+            },
+            "cont" => Self::Cont {
+                pattern: ops.get("pattern")?,
+                step: ops.get("step")?,
+            },
+            "wait" => Self::Wait {
+                ticks: ops.get("ticks")?,
+            },
+            "stop" => Self::Stop,
+            "kup^" => Self::KeyUp {
+                channel: ops.get("ch")?,
+            },
+            "vibr" => Self::Vibrato {
+                speed: ops.get("speed")?,
+                channel: ops.get("ch")?,
+                depth: ops.get("depth")?,
+            },
+            "enve" => Self::Envelope {
+                rate: ops.get("rate")?,
+                interval: ops.get("interval")?,
+                channel: ops.get("ch")?,
+                end_volume: ops.get("end")?,
+            },
+            "gspt" => Self::GoSubPattern {
+                pattern: ops.get("pattern")?,
+                step: ops.get("step")?,
+            },
+            "ropt" => Self::ReturnPattern,
+            "fade" => Self::Fade {
+                speed: ops.get("speed")?,
+                volume: ops.get("volume")?,
+            },
+            "ppat" => Self::PlayPattern {
+                pattern: ops.get("pattern")?,
+                voice: ops.get("voice")?,
+                transpose: ops.get("transpose")?,
+            },
+            "lock" => Self::Lock {
+                flag: ops.get("flag")?,
+                channel: ops.get("ch")?,
+                ticks: ops.get("ticks")?,
+            },
+            "cue" => Self::Cue {
+                index: ops.get("index")?,
+                value: ops.get("value")?,
+            },
+            "stcu" => Self::StopCustom,
+            "nop" => Self::Nop,
+            _ => return Err(Kind::UnknownCommand(name.to_owned())),
+        };
+        ops.finish(command)
+    }
+}
+
+impl FromStr for TrackVoice {
+    type Err = AssembleErrorKind;
+    /// Parse a voice the way it displays
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let invalid = || Kind::InvalidOperand(text.clone());
+        if text == "-- --" {
+            return Ok(Self {
+                pattern: 0xFF,
+                transpose: 0,
+            });
+        }
+        if let Some(channel) = text.to_ascii_lowercase().strip_prefix("fe ch") {
+            return match number(channel) {
+                Ok(channel @ 0..0x10) => Ok(Self {
+                    pattern: 0xFE,
+                    transpose: channel,
+                }),
+                _ => Err(invalid()),
+            };
+        }
+        let (pattern, transpose) = text.split_at_checked(2).ok_or_else(invalid)?;
+        if !transpose.starts_with(['+', '-']) {
+            return Err(invalid());
+        }
+        Ok(Self {
+            pattern: hex(pattern)?,
+            transpose: number(transpose)?,
+        })
+    }
+}
+
+impl FromStr for TrackCommand {
+    type Err = AssembleErrorKind;
+    /// Parse a command the way it displays. Voices left out of a track step don't
+    /// change.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut tokens = text.split_whitespace();
+        let name = tokens.next().unwrap_or_default();
+        let mut ops = Operands::new(tokens);
+        let command = match name.to_ascii_lowercase().as_str() {
+            "stop" => Self::Stop,
+            "loop" => Self::Loop {
+                position: ops.get("position")?,
+                count: ops.get("count")?,
+            },
+            "tempo" => Self::Tempo {
+                speed: ops.get("speed")?,
+                // Other values don't set the timer
+                bpm: match ops.get_opt("bpm")? {
+                    Some(bpm @ (0..0x10 | 0x200..)) => {
+                        return Err(Kind::InvalidOperand(format!("bpm={bpm}")));
+                    }
+                    bpm => bpm,
+                },
+            },
+            "7voice" => Self::SevenVoice {
+                timing: ops
+                    .text("timing")
+                    .map(|timing| number(timing.strip_suffix('%').unwrap_or(timing)))
+                    .transpose()?,
+            },
+            "fade" => Self::Fade {
+                speed: ops.get("speed")?,
+                volume: ops.get("volume")?,
+            },
+            "unknown" => Self::Unknown {
+                command: number(ops.next("command")?)?,
+                operands: [hex(ops.next("operand")?)?, hex(ops.next("operand")?)?],
+            },
+            _ => {
+                let mut voices = [TrackVoice {
+                    pattern: 0xFF,
+                    transpose: 0,
+                }; 8];
+                let mut parsed = text.split('|').map(str::parse);
+                for voice in &mut voices {
+                    match parsed.next() {
+                        Some(parsed) => *voice = parsed?,
+                        None => break,
+                    }
+                }
+                if let Some(extra) = text.split('|').nth(8) {
+                    return Err(Kind::UnexpectedOperand(extra.trim().to_owned()));
+                }
+                return Ok(Self::Patterns(voices));
+            }
+        };
+        ops.finish(command)
+    }
+}
+
+/// Where the steps of the source go
+#[derive(Clone, Copy)]
+enum Section {
+    Tracks,
+    Pattern,
+    Macro,
+}
+
+/// Something a step or a song refers to, which has to exist once the whole source is read
+#[derive(Clone, Copy)]
+enum Reference {
+    TrackStep(u16),
+    Pattern(u8),
+    Macro(u8),
+}
+
+/// Cut off the comment, which starts at a `;` after the last `"`
+fn strip_comment(line: &str) -> &str {
+    let from = line.rfind('"').map_or(0, |quote| quote + 1);
+    line[from..].find(';').map_or(line, |at| &line[..from + at])
+}
+
+/// Skip the step number in front of a step, like `0012:`
+fn strip_step_number(line: &str) -> &str {
+    match line.split_once(':') {
+        Some((num, rest)) if !num.is_empty() && num.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            rest.trim_start()
+        }
+        _ => line,
+    }
+}
+
+/// Split off the bytes in front of a pattern or macro step, like `F0000000`
+fn split_bytes(line: &str) -> (Option<[u8; 4]>, &str) {
+    let first = line.split_whitespace().next().unwrap_or_default();
+    if first.len() != 8 {
+        return (None, line);
+    }
+    match hex::<u32>(first) {
+        Ok(word) if !first.starts_with('$') => (Some(word.to_be_bytes()), line[8..].trim_start()),
+        _ => (None, line),
+    }
+}
+
+/// Split off the words in front of a track step, written like the bytes of four
+/// pattern steps
+fn split_words(line: &str) -> (Option<[u16; 8]>, &str) {
+    let mut words = [0; 8];
+    let mut rest = line;
+    for pair in words.as_chunks_mut::<2>().0 {
+        let (Some([b0, b1, b2, b3]), tail) = split_bytes(rest) else {
+            return (None, line);
+        };
+        *pair = [u16::from_be_bytes([b0, b1]), u16::from_be_bytes([b2, b3])];
+        rest = tail;
+    }
+    (Some(words), rest)
+}
+
+/// The bytes of a step: `bytes` if they're there and decode to `command`, or else the
+/// command encoded
+fn step_bytes<C: FromStr<Err = Kind> + PartialEq>(
+    bytes: Option<[u8; 4]>,
+    command: &str,
+    decode: fn([u8; 4]) -> C,
+    encode: fn(&C) -> [u8; 4],
+) -> Result<[u8; 4], Kind> {
+    match bytes {
+        Some(bytes) if command.is_empty() => Ok(bytes),
+        Some(bytes) => {
+            let command = command.parse()?;
+            Ok(if decode(bytes) == command {
+                bytes
+            } else {
+                encode(&command)
+            })
+        }
+        None => command.parse().map(|command| encode(&command)),
+    }
+}
+
+/// State of [`Module::assemble`]
+struct Assembler {
+    module: Module,
+    text_rows: usize,
+    /// Where steps go
+    section: Option<Section>,
+    references: Vec<(usize, Reference)>,
+}
+
+impl Assembler {
+    fn line(&mut self, line: usize, text: &str) -> Result<(), AssembleError> {
+        let at = |kind| AssembleError { line, kind };
+        let text = strip_comment(text).trim();
+        let Some(directive) = text.split_whitespace().next() else {
+            return Ok(());
+        };
+        let rest = text[directive.len()..].trim_start();
+        match directive.to_ascii_lowercase().as_str() {
+            "text" => self.text(rest).map_err(at),
+            "song" => self.song(line, rest).map_err(at),
+            "tracks" => {
+                self.section = Some(Section::Tracks);
+                Ok(())
+            }
+            "pattern" => {
+                self.section = Some(Section::Pattern);
+                let patterns = &mut self.module.patterns;
+                Self::number(rest, patterns.len()).map_err(at)?;
+                patterns.push(Pattern::default());
+                Ok(())
+            }
+            "macro" => {
+                self.section = Some(Section::Macro);
+                let macros = &mut self.module.macros;
+                Self::number(rest, macros.len()).map_err(at)?;
+                macros.push(Macro::default());
+                Ok(())
+            }
+            _ => self.step(line, strip_step_number(text)).map_err(at),
+        }
+    }
+    /// Check the number of the next pattern or macro
+    fn number(text: &str, expected: usize) -> Result<(), Kind> {
+        let num: usize = hex(text)?;
+        if num >= MAX_ITEMS {
+            return Err(Kind::InvalidOperand(text.to_owned()));
+        }
+        if num != expected {
+            return Err(Kind::OutOfOrder(expected));
+        }
+        Ok(())
+    }
+    fn text(&mut self, rest: &str) -> Result<(), Kind> {
+        let (row, rest) = rest
+            .strip_prefix('"')
+            .and_then(|row| row.rsplit_once('"'))
+            .ok_or(Kind::ExpectedString)?;
+        let mut ops = Operands::new(rest.split_whitespace());
+        let pad = ops.get_opt("pad")?.unwrap_or(b' ');
+        ops.finish(())?;
+        // The text is Latin-1, like on the Amiga
+        let row = row
+            .chars()
+            .map(|c| u8::try_from(c).map_err(|_| Kind::InvalidOperand(c.to_string())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let slot = self
+            .module
+            .text
+            .get_mut(self.text_rows)
+            .filter(|_| row.len() <= usize::from(TEXT_ROW_LEN))
+            .ok_or(Kind::TextTooLong)?;
+        slot.fill(pad);
+        slot[..row.len()].copy_from_slice(&row);
+        self.text_rows += 1;
+        Ok(())
+    }
+    fn song(&mut self, line: usize, rest: &str) -> Result<(), Kind> {
+        let mut ops = Operands::new(rest.split_whitespace());
+        let idx = ops.next("song")?;
+        let song = SongEntry {
+            first_step: ops.get("first")?,
+            last_step: ops.get("last")?,
+            tempo: ops.get("tempo")?,
+        };
+        ops.finish(())?;
+        *number::<usize>(idx)
+            .ok()
+            .and_then(|idx| self.module.songs.get_mut(idx))
+            .ok_or_else(|| Kind::InvalidOperand(idx.to_owned()))? = song;
+        self.references.extend([
+            (line, Reference::TrackStep(song.first_step)),
+            (line, Reference::TrackStep(song.last_step)),
+        ]);
+        Ok(())
+    }
+    fn step(&mut self, line: usize, text: &str) -> Result<(), Kind> {
+        let Some(section) = self.section else {
+            return Err(Kind::StepOutsideSection);
+        };
+        let mut refer = |reference| self.references.push((line, reference));
+        match section {
+            Section::Tracks => {
+                let (words, command) = split_words(text);
+                let words = match words {
+                    Some(words) if command.is_empty() => words,
+                    words => {
+                        let command: TrackCommand = command.parse()?;
+                        words
+                            .filter(|&words| TrackCommand::decode(words) == command)
+                            .unwrap_or_else(|| command.encode())
+                    }
+                };
+                match TrackCommand::decode(words) {
+                    TrackCommand::Patterns(voices) => voices
+                        .iter()
+                        .filter(|voice| voice.pattern < 0x80)
+                        .for_each(|voice| refer(Reference::Pattern(voice.pattern))),
+                    TrackCommand::Loop { position, .. } => refer(Reference::TrackStep(position)),
+                    _ => {}
+                }
+                self.module.tracks.push(words);
+            }
+            Section::Pattern => {
+                let (bytes, command) = split_bytes(text);
+                let bytes = step_bytes(
+                    bytes,
+                    command,
+                    PatternCommand::decode,
+                    PatternCommand::encode,
+                )?;
+                match PatternCommand::decode(bytes) {
+                    PatternCommand::Note { macro_num, .. }
+                    | PatternCommand::NoteWait { macro_num, .. } => {
+                        refer(Reference::Macro(macro_num));
+                    }
+                    PatternCommand::Cont { pattern, .. }
+                    | PatternCommand::GoSubPattern { pattern, .. }
+                    | PatternCommand::PlayPattern { pattern, .. } => {
+                        refer(Reference::Pattern(pattern));
+                    }
+                    _ => {}
+                }
+                if let Some(pattern) = self.module.patterns.last_mut() {
+                    pattern.steps.push(bytes);
+                }
+            }
+            Section::Macro => {
+                let (bytes, command) = split_bytes(text);
+                let bytes = step_bytes(bytes, command, MacroCommand::decode, MacroCommand::encode)?;
+                match MacroCommand::decode(bytes) {
+                    MacroCommand::Cont { macro_num, .. }
+                    | MacroCommand::GoSub { macro_num, .. }
+                    | MacroCommand::PlayMacro { macro_num, .. } => {
+                        refer(Reference::Macro(macro_num));
+                    }
+                    _ => {}
+                }
+                if let Some(mac) = self.module.macros.last_mut() {
+                    mac.steps.push(bytes);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn finish(self) -> Result<Module, AssembleError> {
+        let module = self.module;
+        for (line, reference) in self.references {
+            let kind = match reference {
+                Reference::TrackStep(step) if usize::from(step) >= module.tracks.len() => {
+                    Kind::UndefinedTrackStep(step)
+                }
+                Reference::Pattern(num) if usize::from(num) >= module.patterns.len() => {
+                    Kind::UndefinedPattern(num)
+                }
+                Reference::Macro(num) if usize::from(num) >= module.macros.len() => {
+                    Kind::UndefinedMacro(num)
+                }
+                _ => continue,
+            };
+            return Err(AssembleError { line, kind });
+        }
+        Ok(module)
+    }
+}
+
+impl Module {
+    /// Assemble a module from source text.
+    ///
+    /// Each line has a directive or a step, and comments start with `;`:
+    ///
+    /// - `text "..."` adds a row to the header text, padded with spaces, or with the
+    ///   byte after `pad=`
+    /// - `song 3 first=0 last=7 tempo=5` sets slot 3 of the song table
+    /// - `tracks`, `pattern 00` and `macro 00` start the track table, a pattern or a
+    ///   macro, which get the steps that follow, if there are any. Patterns and macros
+    ///   are numbered in hex, in order.
+    ///
+    /// Steps are written the way [`TrackStep`], [`PatternStep`] and [`MacroStep`]
+    /// display, where the step number and the bytes in front are optional. Track steps
+    /// can have their eight words in front too, written like the bytes of four pattern
+    /// steps. The bytes and words are kept if they decode to the command after them, so
+    /// what [`Self::to_source`] writes assembles to the same steps. Numbers are
+    /// decimal, or hex after `$`.
+    ///
+    /// # Errors
+    ///
+    /// Errors at the first line that doesn't assemble, or that refers to a track step,
+    /// pattern or macro that doesn't exist
+    pub fn assemble(source: &str) -> Result<Self, AssembleError> {
+        let mut assembler = Assembler {
+            module: Self::default(),
+            text_rows: 0,
+            section: None,
+            references: Vec::new(),
+        };
+        for (i, line) in source.lines().enumerate() {
+            assembler.line(i + 1, line)?;
+        }
+        assembler.finish()
+    }
+    /// The module as source text for [`Self::assemble`]. The mute flags of the header
+    /// and the layout of the .mdat data aren't kept.
+    #[must_use]
+    pub fn to_source(&self) -> String {
+        Source(self).to_string()
+    }
+}
+
+/// Source text of a module, see [`Module::to_source`]
+struct Source<'a>(&'a Module);
+
+impl fmt::Display for Source<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let module = self.0;
+        // Rows of spaces at the end are left out, like the assembler pads them
+        let used = module
+            .text
+            .iter()
+            .rposition(|row| row.iter().any(|&b| b != b' '))
+            .map_or(0, |last| last + 1);
+        for row in &module.text[..used] {
+            let pad = match row.last() {
+                Some(&pad @ (b'\0' | b' ')) => pad,
+                _ => b' ',
+            };
+            let len = row
+                .iter()
+                .rposition(|&b| b != pad)
+                .map_or(0, |last| last + 1);
+            let text: String = row[..len].iter().map(|&b| char::from(b)).collect();
+            write!(f, "text \"{text}\"")?;
+            if pad != b' ' {
+                write!(f, " pad={pad}")?;
+            }
+            writeln!(f)?;
+        }
+        for (idx, song) in module.songs.iter().enumerate() {
+            if *song != SongEntry::default() {
+                writeln!(
+                    f,
+                    "song {idx} first={} last={} tempo={}",
+                    song.first_step, song.last_step, song.tempo
+                )?;
+            }
+        }
+        writeln!(f, "\ntracks")?;
+        for (position, &words) in module.tracks.iter().enumerate() {
+            let command = TrackCommand::decode(words);
+            if command.encode() == words {
+                let position = position as u16;
+                let step = TrackStep {
+                    position,
+                    words,
+                    command,
+                };
+                writeln!(f, "    {step}")?;
+                continue;
+            }
+            // Words the command doesn't show go in front
+            write!(f, "    {position:04X}:")?;
+            for pair in words.as_chunks::<2>().0 {
+                write!(f, " {:04X}{:04X}", pair[0], pair[1])?;
+            }
+            writeln!(f, "  {command}")?;
+        }
+        for (num, pattern) in module.patterns.iter().enumerate() {
+            writeln!(f, "\npattern {num:02X}")?;
+            for (step, &bytes) in pattern.steps.iter().enumerate() {
+                let step = PatternStep {
+                    step: step as u16,
+                    bytes,
+                    command: PatternCommand::decode(bytes),
+                };
+                writeln!(f, "    {step}")?;
+            }
+        }
+        for (num, mac) in module.macros.iter().enumerate() {
+            writeln!(f, "\nmacro {num:02X}")?;
+            for (step, &bytes) in mac.steps.iter().enumerate() {
+                let step = MacroStep {
+                    step: step as u16,
+                    bytes,
+                    command: MacroCommand::decode(bytes),
+                };
+                writeln!(f, "    {step}")?;
+            }
+        }
+        Ok(())
+    }
+}
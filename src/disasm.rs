@@ -0,0 +1,1271 @@
+use {
+    crate::{EditBuf, SongIdx, header::Header},
+    std::fmt,
+};
+
+/// Macros and patterns can't have more steps than this
+const MAX_STEPS: usize = 0x1_0000;
+/// Names of the notes of an octave
+pub(crate) const NOTE_NAMES: [&str; 12] = [
+    "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
+];
+
+/// A step of an opcode, a byte and a word
+const fn with_hi(opcode: u8, b1: u8, hi: u16) -> [u8; 4] {
+    let [b2, b3] = hi.to_be_bytes();
+    [opcode, b1, b2, b3]
+}
+
+/// Third byte of a volume step, which skips the step when it's `0xFE`
+const fn skip_byte(skip: bool) -> u8 {
+    if skip { 0xFE } else { 0 }
+}
+
+/// A step of an opcode and a 24 bit address
+const fn with_addr(opcode: u8, addr: u32) -> [u8; 4] {
+    let [_, b1, b2, b3] = addr.to_be_bytes();
+    [opcode, b1, b2, b3]
+}
+
+/// A macro command, with its operands decoded as `run_macro` uses them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroCommand {
+    /// Stop the sample. With `reset`, the effects stop too.
+    DmaOff {
+        /// Whether the effects are reset
+        reset: bool,
+        /// Whether the sample plays to the end of its loop before it stops, instead of
+        /// stopping right away
+        after_loop: bool,
+        /// With `reset`, in GEMX modules: the new volume, see `absolute`
+        volume: u8,
+        /// Whether `volume` is set as it is, rather than added to three times the note
+        /// velocity
+        absolute: bool,
+    },
+    /// Start the sample set up by [`Self::SetBegin`] and [`Self::SetLen`]
+    DmaOn {
+        /// Value for the effect run flag of the channel
+        effects: u8,
+    },
+    /// Set the start of the sample, in bytes from the start of the sample data
+    SetBegin {
+        /// Byte offset into the sample data
+        addr: u32,
+    },
+    /// Set the length of the sample
+    SetLen {
+        /// Length in words
+        len: u16,
+    },
+    /// Wait before running the next step
+    Wait {
+        /// Number of ticks to wait, minus one
+        ticks: u16,
+        /// Whether the wait only lasts until the next tick after the first time, while
+        /// the note plays
+        once: bool,
+    },
+    /// Jump to a step of this macro, `count` times
+    Loop {
+        /// Number of jumps
+        count: u8,
+        /// Step to jump to
+        step: u16,
+    },
+    /// Continue with a step of another macro
+    Cont {
+        /// Macro to continue with
+        macro_num: u8,
+        /// Step of that macro
+        step: u16,
+    },
+    /// Stop running the macro
+    Stop,
+    /// Set the period to the note of the channel plus `note`
+    AddNote {
+        /// Offset added to the note
+        note: i8,
+        /// Detune added to the fine tune of the channel
+        detune: u8,
+    },
+    /// Set the period to a note
+    SetNote {
+        /// The note, 0 to 63
+        note: u8,
+        /// Detune added to the fine tune of the channel
+        detune: u8,
+    },
+    /// Stop the portamento, the vibrato and the envelope
+    Reset,
+    /// Slide the period to the last set note
+    Portamento {
+        /// Ticks between slide steps
+        speed: u8,
+        /// How far each slide step goes
+        rate: i16,
+    },
+    /// Start a vibrato
+    Vibrato {
+        /// Ticks per half period
+        speed: u8,
+        /// How far the period changes per tick
+        depth: i8,
+    },
+    /// Set the volume to three times the note velocity plus `volume`
+    AddVolume {
+        /// Volume added
+        volume: u8,
+        /// Whether the third byte is `0xFE`, which makes the player skip the step
+        skip: bool,
+    },
+    /// Set the volume
+    SetVolume {
+        /// The volume, 0 to 64
+        volume: u8,
+        /// Whether the third byte is `0xFE`, which makes the player skip the step
+        skip: bool,
+    },
+    /// Start a volume envelope
+    Envelope {
+        /// How much the volume changes per step
+        rate: u8,
+        /// Ticks per step
+        interval: u8,
+        /// Volume where the envelope stops
+        end_volume: i8,
+    },
+    /// Like [`Self::Loop`], but only while the key is released
+    LoopKeyUp {
+        /// Number of jumps
+        count: u8,
+        /// Step to jump to
+        step: u16,
+    },
+    /// Move the start of the sample, every `ticks` ticks
+    AddBegin {
+        /// Ticks between moves, or 0 to move once
+        ticks: u8,
+        /// Bytes to move by
+        offset: i16,
+    },
+    /// Change the length of the sample
+    AddLen {
+        /// Words to add
+        offset: i16,
+    },
+    /// Wait for the key to be released, for at most `ticks` ticks
+    WaitKeyUp {
+        /// Most ticks to wait
+        ticks: u8,
+    },
+    /// Run a step of another macro, until [`Self::Return`]
+    GoSub {
+        /// Macro to run
+        macro_num: u8,
+        /// Step of that macro
+        step: u16,
+    },
+    /// Return to the macro that ran [`Self::GoSub`]
+    Return,
+    /// Set the period
+    SetPeriod {
+        /// Amiga period
+        period: u16,
+    },
+    /// Set the loop to start `offset` bytes after the start of the sample
+    SampleLoop {
+        /// Byte offset into the sample
+        offset: u16,
+    },
+    /// Play the sample once, by looping silence after it
+    OneShot,
+    /// Wait until the sample played through `loops` more times
+    WaitOnDma {
+        /// Number of sample loops to wait, minus one
+        loops: u16,
+    },
+    /// Jump to a step if the note of the channel is above `note`
+    SplitKey {
+        /// Highest note that doesn't jump
+        note: u8,
+        /// Step to jump to
+        step: u16,
+    },
+    /// Jump to a step if the volume of the channel is above `volume`
+    SplitVolume {
+        /// Highest volume that doesn't jump
+        volume: u8,
+        /// Step to jump to
+        step: u16,
+    },
+    /// Like [`Self::AddNote`], but relative to the previous note of the channel
+    AddPrevNote {
+        /// Offset added to the note
+        note: i8,
+        /// Detune added to the fine tune of the channel
+        detune: u8,
+    },
+    /// Set a cue value, see [`crate::TfmxPlayer::cues`]
+    Cue {
+        /// Which cue, 0 to 3
+        index: u8,
+        /// The new value
+        value: u16,
+    },
+    /// Play the note of this channel with another macro, on `channel`
+    PlayMacro {
+        /// Macro to play
+        macro_num: u8,
+        /// Channel to play on, and volume in the high nibble
+        channel: u8,
+        /// Detune of the note
+        detune: u8,
+    },
+    /// Set the start that [`Self::AddBegin`] moves, without changing the start of the
+    /// sample
+    SetBeginTemp {
+        /// Byte offset into the sample data
+        addr: u32,
+    },
+    /// An opcode the player doesn't know
+    Unknown {
+        /// The opcode
+        opcode: u8,
+        /// The three operand bytes
+        operand: [u8; 3],
+    },
+}
+
+impl MacroCommand {
+    /// Decode a macro step from its four bytes, as stored in the module
+    #[must_use]
+    pub const fn decode(bytes: [u8; 4]) -> Self {
+        let [opcode, b1, b2, b3] = bytes;
+        let hi = u16::from_be_bytes([b2, b3]);
+        let addr = u32::from_be_bytes([0, b1, b2, b3]);
+        match opcode {
+            0 => Self::DmaOff {
+                reset: true,
+                after_loop: b1 != 0,
+                volume: b3,
+                absolute: b2 != 0,
+            },
+            1 => Self::DmaOn { effects: b1 },
+            2 => Self::SetBegin { addr },
+            3 => Self::SetLen { len: hi },
+            4 => Self::Wait {
+                ticks: hi,
+                once: b1 & 1 != 0,
+            },
+            5 => Self::Loop {
+                count: b1,
+                step: hi,
+            },
+            6 => Self::Cont {
+                macro_num: b1,
+                step: hi,
+            },
+            7 => Self::Stop,
+            8 => Self::AddNote {
+                note: b1 as i8,
+                detune: b3,
+            },
+            9 => Self::SetNote {
+                note: b1,
+                detune: b3,
+            },
+            10 => Self::Reset,
+            11 => Self::Portamento {
+                speed: b1,
+                rate: hi as i16,
+            },
+            12 => Self::Vibrato {
+                speed: b1,
+                depth: b3 as i8,
+            },
+            13 => Self::AddVolume {
+                volume: b3,
+                skip: b2 == 0xFE,
+            },
+            14 => Self::SetVolume {
+                volume: b3,
+                skip: b2 == 0xFE,
+            },
+            15 => Self::Envelope {
+                rate: b1,
+                interval: b2,
+                end_volume: b3 as i8,
+            },
+            16 => Self::LoopKeyUp {
+                count: b1,
+                step: hi,
+            },
+            17 => Self::AddBegin {
+                ticks: b1,
+                offset: hi as i16,
+            },
+            18 => Self::AddLen { offset: hi as i16 },
+            19 => Self::DmaOff {
+                reset: false,
+                after_loop: b1 != 0,
+                volume: 0,
+                absolute: false,
+            },
+            20 => Self::WaitKeyUp { ticks: b3 },
+            21 => Self::GoSub {
+                macro_num: b1,
+                step: hi,
+            },
+            22 => Self::Return,
+            23 => Self::SetPeriod { period: hi },
+            24 => Self::SampleLoop { offset: hi },
+            25 => Self::OneShot,
+            26 => Self::WaitOnDma { loops: hi },
+            28 => Self::SplitKey { note: b1, step: hi },
+            29 => Self::SplitVolume {
+                volume: b1,
+                step: hi,
+            },
+            31 => Self::AddPrevNote {
+                note: b1 as i8,
+                detune: b3,
+            },
+            32 => Self::Cue {
+                index: b1 & 0x3,
+                value: hi,
+            },
+            33 => Self::PlayMacro {
+                macro_num: b1,
+                channel: b2,
+                detune: b3,
+            },
+            34 => Self::SetBeginTemp { addr },
+            _ => Self::Unknown {
+                opcode,
+                operand: [b1, b2, b3],
+            },
+        }
+    }
+    /// Encode the command as the four bytes of a macro step. Bytes and bits the command
+    /// doesn't use are 0, so the step plays and decodes the same as the one the command
+    /// was decoded from, but its bytes can differ.
+    #[must_use]
+    pub const fn encode(&self) -> [u8; 4] {
+        match *self {
+            Self::DmaOff {
+                reset: true,
+                after_loop,
+                volume,
+                absolute,
+            } => [0, after_loop as u8, absolute as u8, volume],
+            Self::DmaOff {
+                reset: false,
+                after_loop,
+                ..
+            } => [19, after_loop as u8, 0, 0],
+            Self::DmaOn { effects } => [1, effects, 0, 0],
+            Self::SetBegin { addr } => with_addr(2, addr),
+            Self::SetLen { len } => with_hi(3, 0, len),
+            Self::Wait { ticks, once } => with_hi(4, once as u8, ticks),
+            Self::Loop { count, step } => with_hi(5, count, step),
+            Self::Cont { macro_num, step } => with_hi(6, macro_num, step),
+            Self::Stop => [7, 0, 0, 0],
+            Self::AddNote { note, detune } => [8, note as u8, 0, detune],
+            Self::SetNote { note, detune } => [9, note, 0, detune],
+            Self::Reset => [10, 0, 0, 0],
+            Self::Portamento { speed, rate } => with_hi(11, speed, rate as u16),
+            Self::Vibrato { speed, depth } => [12, speed, 0, depth as u8],
+            Self::AddVolume { volume, skip } => [13, 0, skip_byte(skip), volume],
+            Self::SetVolume { volume, skip } => [14, 0, skip_byte(skip), volume],
+            Self::Envelope {
+                rate,
+                interval,
+                end_volume,
+            } => [15, rate, interval, end_volume as u8],
+            Self::LoopKeyUp { count, step } => with_hi(16, count, step),
+            Self::AddBegin { ticks, offset } => with_hi(17, ticks, offset as u16),
+            Self::AddLen { offset } => with_hi(18, 0, offset as u16),
+            Self::WaitKeyUp { ticks } => [20, 0, 0, ticks],
+            Self::GoSub { macro_num, step } => with_hi(21, macro_num, step),
+            Self::Return => [22, 0, 0, 0],
+            Self::SetPeriod { period } => with_hi(23, 0, period),
+            Self::SampleLoop { offset } => with_hi(24, 0, offset),
+            Self::OneShot => [25, 0, 0, 0],
+            Self::WaitOnDma { loops } => with_hi(26, 0, loops),
+            Self::SplitKey { note, step } => with_hi(28, note, step),
+            Self::SplitVolume { volume, step } => with_hi(29, volume, step),
+            Self::AddPrevNote { note, detune } => [31, note as u8, 0, detune],
+            Self::Cue { index, value } => with_hi(32, index, value),
+            Self::PlayMacro {
+                macro_num,
+                channel,
+                detune,
+            } => [33, macro_num, channel, detune],
+            Self::SetBeginTemp { addr } => with_addr(34, addr),
+            Self::Unknown {
+                opcode,
+                operand: [b1, b2, b3],
+            } => [opcode, b1, b2, b3],
+        }
+    }
+    /// Name of the command, as TFMX editors show it
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::DmaOff { reset: true, .. } => "DMAoff+Reset",
+            Self::DmaOff { reset: false, .. } => "DMAoff",
+            Self::DmaOn { .. } => "DMAon",
+            Self::SetBegin { .. } => "SetBegin",
+            Self::SetLen { .. } => "SetLen",
+            Self::Wait { .. } => "Wait",
+            Self::Loop { .. } => "Loop",
+            Self::Cont { .. } => "Cont",
+            Self::Stop => "Stop",
+            Self::AddNote { .. } => "AddNote",
+            Self::SetNote { .. } => "SetNote",
+            Self::Reset => "Reset",
+            Self::Portamento { .. } => "Portamento",
+            Self::Vibrato { .. } => "Vibrato",
+            Self::AddVolume { .. } => "AddVolume",
+            Self::SetVolume { .. } => "SetVolume",
+            Self::Envelope { .. } => "Envelope",
+            Self::LoopKeyUp { .. } => "LoopKeyUp",
+            Self::AddBegin { .. } => "AddBegin",
+            Self::AddLen { .. } => "AddLen",
+            Self::WaitKeyUp { .. } => "WaitKeyUp",
+            Self::GoSub { .. } => "GoSub",
+            Self::Return => "Return",
+            Self::SetPeriod { .. } => "SetPeriod",
+            Self::SampleLoop { .. } => "SampleLoop",
+            Self::OneShot => "OneShot",
+            Self::WaitOnDma { .. } => "WaitOnDMA",
+            Self::SplitKey { .. } => "SplitKey",
+            Self::SplitVolume { .. } => "SplitVolume",
+            Self::AddPrevNote { .. } => "AddPrevNote",
+            Self::Cue { .. } => "Cue",
+            Self::PlayMacro { .. } => "PlayMacro",
+            Self::SetBeginTemp { .. } => "SetBeginTemp",
+            Self::Unknown { .. } => "Unknown",
+        }
+    }
+    /// Whether the player doesn't know the opcode
+    #[must_use]
+    pub const fn is_unknown(&self) -> bool {
+        matches!(self, Self::Unknown { .. })
+    }
+}
+
+impl fmt::Display for MacroCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())?;
+        match *self {
+            Self::DmaOff {
+                reset,
+                after_loop,
+                volume,
+                absolute,
+            } => {
+                if after_loop {
+                    f.write_str(" after_loop")?;
+                }
+                if reset && (volume != 0 || absolute) {
+                    write!(f, " volume={volume}")?;
+                }
+                if reset && absolute {
+                    f.write_str(" absolute")?;
+                }
+                Ok(())
+            }
+            Self::Stop | Self::Reset | Self::Return | Self::OneShot => Ok(()),
+            Self::DmaOn { effects } => write!(f, " effects={effects}"),
+            Self::SetBegin { addr } | Self::SetBeginTemp { addr } => write!(f, " ${addr:06X}"),
+            Self::SetLen { len } => write!(f, " len=${len:04X}"),
+            Self::Wait { ticks, once } => {
+                write!(f, " ticks={ticks}")?;
+                if once {
+                    f.write_str(" once")?;
+                }
+                Ok(())
+            }
+            Self::Loop { count, step } | Self::LoopKeyUp { count, step } => {
+                write!(f, " count={count} step={step}")
+            }
+            Self::Cont { macro_num, step } | Self::GoSub { macro_num, step } => {
+                write!(f, " macro=${macro_num:02X} step={step}")
+            }
+            Self::AddNote { note, detune } | Self::AddPrevNote { note, detune } => {
+                write!(f, " note={note:+} detune={detune}")
+            }
+            Self::SetNote { note, detune } => write!(f, " note={note} detune={detune}"),
+            Self::Portamento { speed, rate } => write!(f, " speed={speed} rate={rate}"),
+            Self::Vibrato { speed, depth } => write!(f, " speed={speed} depth={depth}"),
+            Self::AddVolume { volume, skip } | Self::SetVolume { volume, skip } => {
+                write!(f, " volume={volume}")?;
+                if skip {
+                    f.write_str(" skip")?;
+                }
+                Ok(())
+            }
+            Self::Envelope {
+                rate,
+                interval,
+                end_volume,
+            } => write!(f, " rate={rate} interval={interval} end={end_volume}"),
+            Self::AddBegin { ticks, offset } => write!(f, " ticks={ticks} offset={offset:+}"),
+            Self::AddLen { offset } => write!(f, " offset={offset:+}"),
+            Self::WaitKeyUp { ticks } => write!(f, " ticks={ticks}"),
+            Self::SetPeriod { period } => write!(f, " period={period}"),
+            Self::SampleLoop { offset } => write!(f, " offset=${offset:04X}"),
+            Self::WaitOnDma { loops } => write!(f, " loops={loops}"),
+            Self::SplitKey { note, step } => write!(f, " note={note} step={step}"),
+            Self::SplitVolume { volume, step } => write!(f, " volume={volume} step={step}"),
+            Self::Cue { index, value } => write!(f, " index={index} value=${value:04X}"),
+            Self::PlayMacro {
+                macro_num,
+                channel,
+                detune,
+            } => write!(
+                f,
+                " macro=${macro_num:02X} channel=${channel:02X} detune={detune}"
+            ),
+            Self::Unknown {
+                opcode,
+                operand: [b1, b2, b3],
+            } => write!(f, " ${opcode:02X} {b1:02X}{b2:02X}{b3:02X}"),
+        }
+    }
+}
+
+/// A step of a disassembled macro
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacroStep {
+    /// Index of the step in the macro
+    pub step: u16,
+    /// The step as stored in the module
+    pub bytes: [u8; 4],
+    /// The decoded command
+    pub command: MacroCommand,
+}
+
+impl fmt::Display for MacroStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [b0, b1, b2, b3] = self.bytes;
+        write!(
+            f,
+            "{:04X}: {b0:02X}{b1:02X}{b2:02X}{b3:02X}  {}",
+            self.step, self.command
+        )?;
+        if self.command.is_unknown() {
+            f.write_str("  ; unknown opcode")?;
+        }
+        Ok(())
+    }
+}
+
+/// The words of the macro or pattern that starts at edit buffer index `start`, as
+/// bytes, numbered by step.
+///
+/// It ends where the next macro or pattern in the edit buffer starts, or at the first
+/// opcode `is_last` accepts if there's no next one.
+fn table_words(
+    header: &Header,
+    editbuf: &EditBuf,
+    start: usize,
+    is_last: impl Fn(u8) -> bool,
+) -> impl Iterator<Item = (u16, [u8; 4])> {
+    let tables = [
+        (header.macro_start, header.macro_count),
+        (header.patt_start, header.pattern_count),
+    ];
+    let next = tables
+        .into_iter()
+        .flat_map(|(table, count)| editbuf[table..table + count].iter().map(|&i| i as usize))
+        .filter(|&i| i > start)
+        .min();
+    let end = next
+        .unwrap_or(editbuf.len())
+        .min(editbuf.len())
+        .min(start + MAX_STEPS);
+    let mut stopped = false;
+    editbuf[start.min(end)..end]
+        .iter()
+        .take_while(move |&&word| {
+            let go = word != u32::MAX && !stopped;
+            stopped = next.is_none() && is_last((u32::from_be(word) >> 24) as u8);
+            go
+        })
+        .enumerate()
+        .map(|(step, &word)| (step as u16, u32::from_be(word).to_be_bytes()))
+}
+
+/// The steps of macro `num`, with their bytes
+pub(crate) fn macro_words(
+    header: &Header,
+    editbuf: &EditBuf,
+    num: u8,
+) -> Option<impl Iterator<Item = (u16, [u8; 4])>> {
+    if usize::from(num) >= header.macro_count {
+        return None;
+    }
+    let start = editbuf[header.macro_start + usize::from(num)] as usize;
+    Some(table_words(header, editbuf, start, |opcode| opcode == 7))
+}
+
+/// Disassemble macro `num`, or return `None` if there's no such macro
+pub(crate) fn disassemble_macro(
+    header: &Header,
+    editbuf: &EditBuf,
+    num: u8,
+) -> Option<Vec<MacroStep>> {
+    let words = macro_words(header, editbuf, num)?;
+    Some(
+        words
+            .map(|(step, bytes)| MacroStep {
+                step,
+                bytes,
+                command: MacroCommand::decode(bytes),
+            })
+            .collect(),
+    )
+}
+
+/// Name of a note, like `C#2`, where note 0 is `C-0`
+fn note_name(note: u8) -> String {
+    format!("{}{}", NOTE_NAMES[usize::from(note % 12)], note / 12)
+}
+
+/// A pattern command, with its operands decoded as `do_track` uses them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternCommand {
+    /// Play a note
+    Note {
+        /// The note before transposing, 0 to 63
+        note: u8,
+        /// Macro that plays the note
+        macro_num: u8,
+        /// Volume, 0 to 15
+        volume: u8,
+        /// Channel to play on
+        channel: u8,
+        /// Detune of the note
+        detune: u8,
+    },
+    /// Play a note, then wait
+    NoteWait {
+        /// The note before transposing, 0 to 63
+        note: u8,
+        /// Macro that plays the note
+        macro_num: u8,
+        /// Volume, 0 to 15
+        volume: u8,
+        /// Channel to play on
+        channel: u8,
+        /// Ticks to wait
+        wait: u8,
+    },
+    /// Slide the channel to a note
+    Portamento {
+        /// The note before transposing, 0 to 63
+        note: u8,
+        /// Ticks between slide steps
+        speed: u8,
+        /// Channel that slides
+        channel: u8,
+        /// How far each slide step goes
+        rate: u8,
+    },
+    /// End the pattern and go to the next track step
+    End,
+    /// Jump to a step of the pattern, `count` times
+    Loop {
+        /// Number of jumps
+        count: u8,
+        /// Step to jump to
+        step: u16,
+    },
+    /// Continue with a step of another pattern
+    Cont {
+        /// Pattern to continue with
+        pattern: u8,
+        /// Step of that pattern
+        step: u16,
+    },
+    /// Wait before running the next step
+    Wait {
+        /// Number of ticks to wait
+        ticks: u8,
+    },
+    /// Stop the pattern
+    Stop,
+    /// Release the note on a channel
+    KeyUp {
+        /// Channel of the note
+        channel: u8,
+    },
+    /// Start a vibrato on a channel
+    Vibrato {
+        /// Ticks per period
+        speed: u8,
+        /// Channel with the vibrato
+        channel: u8,
+        /// How far the period changes per tick
+        depth: i8,
+    },
+    /// Start a volume envelope on a channel
+    Envelope {
+        /// How much the volume changes per step
+        rate: u8,
+        /// Ticks per step, minus one
+        interval: u8,
+        /// Channel with the envelope
+        channel: u8,
+        /// Volume where the envelope stops
+        end_volume: i8,
+    },
+    /// Continue with a step of another pattern, until [`Self::ReturnPattern`]
+    GoSubPattern {
+        /// Pattern to run
+        pattern: u8,
+        /// Step of that pattern
+        step: u16,
+    },
+    /// Return to the pattern that ran [`Self::GoSubPattern`]
+    ReturnPattern,
+    /// Fade the master volume
+    Fade {
+        /// Ticks per volume step
+        speed: u8,
+        /// Volume to fade to
+        volume: u8,
+    },
+    /// Start a pattern on another voice of the sequencer
+    PlayPattern {
+        /// Pattern to start
+        pattern: u8,
+        /// Voice of the sequencer
+        voice: u8,
+        /// Transpose of the notes of the pattern
+        transpose: i8,
+    },
+    /// Keep notes off a channel
+    Lock {
+        /// Lock flag of the channel
+        flag: u8,
+        /// Channel to lock
+        channel: u8,
+        /// How many macro ticks the lock lasts
+        ticks: u8,
+    },
+    /// Set a cue value, see [`crate::TfmxPlayer::cues`]
+    Cue {
+        /// Which cue, 0 to 3
+        index: u8,
+        /// The new value
+        value: u16,
+    },
+    /// Stop the pattern, and the custom pattern that plays in its place
+    StopCustom,
+    /// Do nothing
+    Nop,
+}
+
+impl PatternCommand {
+    /// Decode a pattern step from its four bytes, as stored in the module
+    #[must_use]
+    pub const fn decode(bytes: [u8; 4]) -> Self {
+        let [b0, b1, b2, b3] = bytes;
+        let hi = u16::from_be_bytes([b2, b3]);
+        let note = b0 & 0x3F;
+        let (volume, channel) = (b2 >> 4, b2 & 0xF);
+        if b0 < 0xF0 {
+            return match b0 & 0xC0 {
+                0x80 => Self::NoteWait {
+                    note,
+                    macro_num: b1,
+                    volume,
+                    channel,
+                    wait: b3,
+                },
+                0xC0 => Self::Portamento {
+                    note,
+                    speed: b1,
+                    channel,
+                    rate: b3,
+                },
+                _ => Self::Note {
+                    note,
+                    macro_num: b1,
+                    volume,
+                    channel,
+                    detune: b3,
+                },
+            };
+        }
+        match b0 & 0xF {
+            0 => Self::End,
+            1 => Self::Loop {
+                count: b1,
+                step: hi,
+            },
+            2 => Self::Cont {
+                pattern: b1,
+                step: hi,
+            },
+            3 => Self::Wait { ticks: b1 },
+            4 => Self::Stop,
+            5 => Self::KeyUp { channel },
+            6 => Self::Vibrato {
+                speed: b1,
+                channel,
+                depth: b3 as i8,
+            },
+            7 => Self::Envelope {
+                rate: b1,
+                interval: volume,
+                channel,
+                end_volume: b3 as i8,
+            },
+            8 => Self::GoSubPattern {
+                pattern: b1,
+                step: hi,
+            },
+            9 => Self::ReturnPattern,
+            10 => Self::Fade {
+                speed: b1,
+                volume: b3,
+            },
+            11 => Self::PlayPattern {
+                pattern: b1,
+                voice: b2 & 0x7,
+                transpose: b3 as i8,
+            },
+            12 => Self::Lock {
+                flag: b1,
+                channel,
+                ticks: b3,
+            },
+            13 => Self::Cue {
+                index: b1 & 0x3,
+                value: hi,
+            },
+            14 => Self::StopCustom,
+            _ => Self::Nop,
+        }
+    }
+    /// Encode the command as the four bytes of a pattern step. Bytes and bits the command
+    /// doesn't use are 0, so the step plays and decodes the same as the one the command
+    /// was decoded from, but its bytes can differ.
+    #[must_use]
+    pub const fn encode(&self) -> [u8; 4] {
+        match *self {
+            Self::Note {
+                note,
+                macro_num,
+                volume,
+                channel,
+                detune,
+            } => [note & 0x3F, macro_num, volume << 4 | channel & 0xF, detune],
+            Self::NoteWait {
+                note,
+                macro_num,
+                volume,
+                channel,
+                wait,
+            } => [
+                0x80 | note & 0x3F,
+                macro_num,
+                volume << 4 | channel & 0xF,
+                wait,
+            ],
+            Self::Portamento {
+                note,
+                speed,
+                channel,
+                rate,
+            } => [0xC0 | note & 0x3F, speed, channel, rate],
+            Self::End => [0xF0, 0, 0, 0],
+            Self::Loop { count, step } => with_hi(0xF1, count, step),
+            Self::Cont { pattern, step } => with_hi(0xF2, pattern, step),
+            Self::Wait { ticks } => [0xF3, ticks, 0, 0],
+            Self::Stop => [0xF4, 0, 0, 0],
+            Self::KeyUp { channel } => [0xF5, 0, channel, 0],
+            Self::Vibrato {
+                speed,
+                channel,
+                depth,
+            } => [0xF6, speed, channel, depth as u8],
+            Self::Envelope {
+                rate,
+                interval,
+                channel,
+                end_volume,
+            } => [0xF7, rate, interval << 4 | channel & 0xF, end_volume as u8],
+            Self::GoSubPattern { pattern, step } => with_hi(0xF8, pattern, step),
+            Self::ReturnPattern => [0xF9, 0, 0, 0],
+            Self::Fade { speed, volume } => [0xFA, speed, 0, volume],
+            Self::PlayPattern {
+                pattern,
+                voice,
+                transpose,
+            } => [0xFB, pattern, voice, transpose as u8],
+            Self::Lock {
+                flag,
+                channel,
+                ticks,
+            } => [0xFC, flag, channel, ticks],
+            Self::Cue { index, value } => with_hi(0xFD, index, value),
+            Self::StopCustom => [0xFE, 0, 0, 0],
+            Self::Nop => [0xFF, 0, 0, 0],
+        }
+    }
+    /// Name of the command, as TFMX editors show it, or `None` for notes
+    #[must_use]
+    pub const fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Note { .. } | Self::NoteWait { .. } | Self::Portamento { .. } => return None,
+            Self::End => "End",
+            Self::Loop { .. } => "Loop",
+            Self::Cont { .. } => "Cont",
+            Self::Wait { .. } => "Wait",
+            Self::Stop => "Stop",
+            Self::KeyUp { .. } => "Kup^",
+            Self::Vibrato { .. } => "Vibr",
+            Self::Envelope { .. } => "Enve",
+            Self::GoSubPattern { .. } => "GsPt",
+            Self::ReturnPattern => "RoPt",
+            Self::Fade { .. } => "Fade",
+            Self::PlayPattern { .. } => "PPat",
+            Self::Lock { .. } => "Lock",
+            Self::Cue { .. } => "Cue",
+            Self::StopCustom => "StCu",
+            Self::Nop => "NOP",
+        })
+    }
+}
+
+impl fmt::Display for PatternCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Note {
+                note,
+                macro_num,
+                volume,
+                channel,
+                detune,
+            } => {
+                let note = note_name(note);
+                write!(
+                    f,
+                    "{note} {macro_num:02X} {volume:X}{channel:X} {detune:02X}"
+                )
+            }
+            Self::NoteWait {
+                note,
+                macro_num,
+                volume,
+                channel,
+                wait,
+            } => {
+                let note = note_name(note);
+                write!(
+                    f,
+                    "{note} {macro_num:02X} {volume:X}{channel:X} wait={wait}"
+                )
+            }
+            Self::Portamento {
+                note,
+                speed,
+                channel,
+                rate,
+            } => {
+                let note = note_name(note);
+                write!(f, "{note} porta ch={channel} speed={speed} rate={rate}")
+            }
+            _ => {
+                f.write_str(self.name().unwrap_or_default())?;
+                match *self {
+                    Self::Loop { count, step } => write!(f, " count={count} step={step}"),
+                    Self::Cont { pattern, step } | Self::GoSubPattern { pattern, step } => {
+                        write!(f, " pattern=${pattern:02X} step={step}")
+                    }
+                    Self::Wait { ticks } => write!(f, " ticks={ticks}"),
+                    Self::KeyUp { channel } => write!(f, " ch={channel}"),
+                    Self::Vibrato {
+                        speed,
+                        channel,
+                        depth,
+                    } => write!(f, " ch={channel} speed={speed} depth={depth}"),
+                    Self::Envelope {
+                        rate,
+                        interval,
+                        channel,
+                        end_volume,
+                    } => write!(
+                        f,
+                        " ch={channel} rate={rate} interval={interval} end={end_volume}"
+                    ),
+                    Self::Fade { speed, volume } => write!(f, " speed={speed} volume={volume}"),
+                    Self::PlayPattern {
+                        pattern,
+                        voice,
+                        transpose,
+                    } => write!(
+                        f,
+                        " pattern=${pattern:02X} voice={voice} transpose={transpose}"
+                    ),
+                    Self::Lock {
+                        flag,
+                        channel,
+                        ticks,
+                    } => write!(f, " ch={channel} flag={flag} ticks={ticks}"),
+                    Self::Cue { index, value } => write!(f, " index={index} value=${value:04X}"),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// A step of a disassembled pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatternStep {
+    /// Index of the step in the pattern
+    pub step: u16,
+    /// The step as stored in the module
+    pub bytes: [u8; 4],
+    /// The decoded command
+    pub command: PatternCommand,
+}
+
+impl fmt::Display for PatternStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [b0, b1, b2, b3] = self.bytes;
+        write!(
+            f,
+            "{:04X}: {b0:02X}{b1:02X}{b2:02X}{b3:02X}  {}",
+            self.step, self.command
+        )
+    }
+}
+
+/// Disassemble pattern `num`, or return `None` if there's no such pattern
+pub(crate) fn disassemble_pattern(
+    header: &Header,
+    editbuf: &EditBuf,
+    num: u8,
+) -> Option<Vec<PatternStep>> {
+    if usize::from(num) >= header.pattern_count {
+        return None;
+    }
+    let start = editbuf[header.patt_start + usize::from(num)] as usize;
+    Some(
+        table_words(header, editbuf, start, |opcode| {
+            opcode == 0xF0 || opcode == 0xF4
+        })
+        .map(|(step, bytes)| PatternStep {
+            step,
+            bytes,
+            command: PatternCommand::decode(bytes),
+        })
+        .collect(),
+    )
+}
+
+/// What a sequencer voice does at a track step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackVoice {
+    /// Pattern to start. From `0x80`, the voice keeps its pattern, which only plays on
+    /// below `0x90`. `0xFE` stops the channel in the low nibble of `transpose`.
+    pub pattern: u8,
+    /// Transpose of the notes of the pattern
+    pub transpose: i8,
+}
+
+impl fmt::Display for TrackVoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.pattern, self.transpose) {
+            (0xFF, 0) => f.write_str("-- --"),
+            (0xFE, 0..0x10) => write!(f, "FE ch{}", self.transpose),
+            _ => write!(f, "{:02X}{:+03}", self.pattern, self.transpose),
+        }
+    }
+}
+
+/// A command of the track table, with its operands decoded as `get_track_step` uses them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackCommand {
+    /// Start patterns on the voices of the sequencer
+    Patterns([TrackVoice; 8]),
+    /// Stop the song
+    Stop,
+    /// Jump to a track step, `count` times
+    Loop {
+        /// Track step to jump to
+        position: u16,
+        /// Number of jumps minus one, or `0xFFFF` to jump forever
+        count: u16,
+    },
+    /// Set the tempo
+    Tempo {
+        /// Ticks per pattern step, minus one
+        speed: u16,
+        /// Tempo in BPM that sets the timer, if there is one
+        bpm: Option<u16>,
+    },
+    /// Switch to 7 voice mode
+    SevenVoice {
+        /// How much faster the timer runs, in percent, if it changes
+        timing: Option<i8>,
+    },
+    /// Fade the master volume
+    Fade {
+        /// Ticks per volume step
+        speed: u8,
+        /// Volume to fade to
+        volume: u8,
+    },
+    /// A command the player skips
+    Unknown {
+        /// The command number
+        command: u16,
+        /// The operands
+        operands: [u16; 2],
+    },
+}
+
+impl TrackCommand {
+    /// Decode a track step from its eight words
+    #[must_use]
+    pub fn decode(words: [u16; 8]) -> Self {
+        if words[0] != 0xEFFE {
+            return Self::Patterns(words.map(|word| TrackVoice {
+                pattern: (word >> 8) as u8,
+                transpose: word as i8,
+            }));
+        }
+        let [_, command, a, b, ..] = words;
+        match command {
+            0 => Self::Stop,
+            1 => Self::Loop {
+                position: a,
+                count: b,
+            },
+            2 => Self::Tempo {
+                speed: a,
+                bpm: Some(b & 0x1FF).filter(|&bpm| b & 0xF200 == 0 && bpm > 0xF),
+            },
+            3 => Self::SevenVoice {
+                timing: (b & 0x8000 == 0).then_some((b as i8).max(-0x20)),
+            },
+            4 => Self::Fade {
+                speed: a as u8,
+                volume: b as u8,
+            },
+            _ => Self::Unknown {
+                command,
+                operands: [a, b],
+            },
+        }
+    }
+    /// Encode the command as the eight words of a track step. Words and operand bits the
+    /// command doesn't use are 0, so the step plays and decodes the same as the one the
+    /// command was decoded from, but its words can differ.
+    #[must_use]
+    pub fn encode(&self) -> [u16; 8] {
+        let (command, a, b) = match *self {
+            Self::Patterns(voices) => {
+                return voices
+                    .map(|voice| u16::from(voice.pattern) << 8 | u16::from(voice.transpose as u8));
+            }
+            Self::Stop => (0, 0, 0),
+            Self::Loop { position, count } => (1, position, count),
+            Self::Tempo { speed, bpm } => (2, speed, bpm.unwrap_or(0)),
+            Self::SevenVoice { timing } => (3, 0, timing.map_or(0x8000, |t| u16::from(t as u8))),
+            Self::Fade { speed, volume } => (4, u16::from(speed), u16::from(volume)),
+            Self::Unknown {
+                command,
+                operands: [a, b],
+            } => (command, a, b),
+        };
+        [0xEFFE, command, a, b, 0, 0, 0, 0]
+    }
+}
+
+impl fmt::Display for TrackCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Patterns(voices) => {
+                for (i, voice) in voices.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" | ")?;
+                    }
+                    write!(f, "{voice}")?;
+                }
+                Ok(())
+            }
+            Self::Stop => f.write_str("Stop"),
+            Self::Loop { position, count } => write!(f, "Loop position={position} count={count}"),
+            Self::Tempo { speed, bpm } => {
+                write!(f, "Tempo speed={speed}")?;
+                bpm.map_or(Ok(()), |bpm| write!(f, " bpm={bpm}"))
+            }
+            Self::SevenVoice { timing } => {
+                f.write_str("7Voice")?;
+                timing.map_or(Ok(()), |timing| write!(f, " timing={timing:+}%"))
+            }
+            Self::Fade { speed, volume } => write!(f, "Fade speed={speed} volume={volume}"),
+            Self::Unknown {
+                command,
+                operands: [a, b],
+            } => write!(f, "Unknown ${command:04X} {a:04X} {b:04X}"),
+        }
+    }
+}
+
+/// A disassembled step of the track table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackStep {
+    /// Index of the step in the track table
+    pub position: u16,
+    /// The step as stored in the module
+    pub words: [u16; 8],
+    /// The decoded command
+    pub command: TrackCommand,
+}
+
+impl fmt::Display for TrackStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X}: {}", self.position, self.command)
+    }
+}
+
+/// Disassemble the track steps of subsong `idx`, or return `None` if the song table
+/// slot doesn't point into the track table
+pub(crate) fn disassemble_track(
+    header: &Header,
+    editbuf: &EditBuf,
+    idx: SongIdx,
+) -> Option<Vec<TrackStep>> {
+    let idx = usize::from(idx);
+    let (first, last) = (*header.song_starts.get(idx)?, *header.song_ends.get(idx)?);
+    // The track table ends where the first pattern starts
+    let table_len = (editbuf[header.patt_start] as usize).saturating_sub(header.track_start) / 4;
+    if first > last || usize::from(last) >= table_len {
+        return None;
+    }
+    let words: &[u16] = bytemuck::cast_slice(&editbuf[header.track_start..]);
+    Some(
+        (first..=last)
+            .map(|position| {
+                let start = usize::from(position) * 8;
+                let words: [u16; 8] = words[start..start + 8].try_into().unwrap();
+                TrackStep {
+                    position,
+                    words,
+                    command: TrackCommand::decode(words),
+                }
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,69 @@
+use {
+    crate::{SongIdx, TfmxCtx, header::Header, rendering::run_tick},
+    std::time::Duration,
+};
+
+/// Songs that don't end or loop within this many seconds are assumed to go on forever
+pub(crate) const MAX_ESTIMATE_SECS: u64 = 60 * 60;
+
+/// How long a subsong lasts, as estimated by [`crate::TfmxPlayer::estimate_duration`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SongDuration {
+    /// Length of the song.
+    ///
+    /// For songs that loop, this is the length of the first pass, after which the song
+    /// jumps back to [`Self::loop_start`].
+    pub length: Duration,
+    /// Where the looping part of the song starts, if the song loops instead of ending
+    pub loop_start: Option<Duration>,
+}
+
+/// Run the sequencer through the first pass of a song without mixing, timing it with
+/// the same tick math as the renderer.
+///
+/// The length ends at the start of the tick that begins the second pass, which
+/// [`run_tick`] doesn't count, so it matches the number of samples rendered.
+pub(crate) fn estimate(header: &Header, clean_tfmx: &TfmxCtx, idx: SongIdx) -> SongDuration {
+    let rate = clean_tfmx.out_rate;
+    let tfmx = run_song(
+        header,
+        clean_tfmx,
+        idx,
+        1,
+        MAX_ESTIMATE_SECS * u64::from(rate),
+    );
+    SongDuration {
+        length: samples_to_duration(tfmx.loop_tracker.clock, rate),
+        loop_start: (tfmx.loop_tracker.loop_start).map(|start| samples_to_duration(start, rate)),
+    }
+}
+
+/// Run the sequencer without mixing until the song ends, completes `max_passes` passes,
+/// or `max_samples` samples are reached, and return the context at that point.
+pub(crate) fn run_song(
+    header: &Header,
+    clean_tfmx: &TfmxCtx,
+    idx: SongIdx,
+    max_passes: u32,
+    max_samples: u64,
+) -> TfmxCtx {
+    let mut tfmx = clean_tfmx.start_copy(header, idx, max_passes);
+    let mut e_rem = 0;
+    while tfmx.mdb.player_enable && tfmx.loop_tracker.clock < max_samples {
+        run_tick(header, &mut tfmx, &mut e_rem);
+    }
+    tfmx
+}
+
+/// Convert a number of (per channel) samples at `rate` to a [`Duration`]
+pub(crate) fn samples_to_duration(samples: u64, rate: u32) -> Duration {
+    let rate = u64::from(rate);
+    let nanos = (samples % rate) * 1_000_000_000 / rate;
+    Duration::new(samples / rate, nanos as u32)
+}
+
+/// Convert a [`Duration`] to a number of (per channel) samples at `rate`
+pub(crate) fn duration_to_samples(duration: Duration, rate: u32) -> u64 {
+    let rate = u64::from(rate);
+    duration.as_secs() * rate + u64::from(duration.subsec_nanos()) * rate / 1_000_000_000
+}
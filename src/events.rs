@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+
+/// How many events are kept for [`crate::TfmxPlayer::drain_events`] at most, before the
+/// oldest ones get dropped
+const MAX_QUEUED: usize = 4096;
+
+/// Something that happened in the song, as reported by [`crate::TfmxPlayer::drain_events`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// Position of the event in the output, in frames since the song started
+    pub frame: u64,
+    /// What happened
+    pub kind: EventKind,
+}
+
+/// What kind of [`Event`] happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A macro or a pattern changed a cue value, see [`crate::TfmxPlayer::cues`]
+    CueChanged {
+        /// Which cue changed, 0 to 3
+        index: u8,
+        /// The new value
+        value: u16,
+    },
+    /// A note started playing
+    NoteOn {
+        /// Channel the note plays on
+        channel: u8,
+        /// Note number, 0 to 63
+        note: u8,
+        /// Macro that plays the note
+        macro_num: u8,
+        /// Volume of the note, 0 to 15
+        velocity: u8,
+        /// Detune of the note
+        fine_tune: i8,
+    },
+    /// A channel started sliding to another note
+    Portamento {
+        /// Channel that slides
+        channel: u8,
+        /// Note to slide to, 0 to 63
+        note: u8,
+        /// Ticks between slide steps
+        speed: u8,
+        /// How far each slide step goes
+        rate: u8,
+    },
+    /// A channel started a vibrato
+    Vibrato {
+        /// Channel with the vibrato
+        channel: u8,
+        /// Ticks per half period
+        speed: u8,
+        /// How far the period changes per tick
+        depth: i8,
+    },
+    /// A channel started a volume envelope
+    Envelope {
+        /// Channel with the envelope
+        channel: u8,
+        /// How much the volume changes per step
+        rate: u8,
+        /// Ticks per step
+        interval: u8,
+        /// Volume where the envelope stops
+        end_volume: i8,
+    },
+    /// The note on a channel was released
+    KeyUp {
+        /// Channel of the note
+        channel: u8,
+    },
+    /// A voice of the sequencer started a pattern
+    PatternChange {
+        /// Voice of the sequencer, 0 to 7
+        voice: u8,
+        /// Pattern number
+        pattern: u8,
+        /// Transpose of the notes of the pattern
+        transpose: i8,
+    },
+    /// The sequencer went to a track step that plays patterns
+    TrackStep {
+        /// Index of the track step
+        position: u16,
+    },
+    /// The tempo was set. This also happens when a song starts.
+    TempoChange {
+        /// Ticks per pattern step, minus one
+        speed: u16,
+        /// Length of a tick in CIA timer clocks
+        e_clocks: u32,
+    },
+}
+
+/// Events waiting to be taken by the user
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventQueue {
+    events: VecDeque<Event>,
+}
+
+impl EventQueue {
+    pub(crate) fn push(&mut self, frame: u64, kind: EventKind) {
+        if self.events.len() == MAX_QUEUED {
+            self.events.pop_front();
+        }
+        self.events.push_back(Event { frame, kind });
+    }
+    /// Drop the events before `frame`
+    pub(crate) fn discard_before(&mut self, frame: u64) {
+        self.events.retain(|event| event.frame >= frame);
+    }
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = Event> {
+        self.events.drain(..)
+    }
+}
@@ -0,0 +1,131 @@
+use std::f32::consts::{PI, SQRT_2};
+
+/// Which Amiga's output filters to emulate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AmigaModel {
+    /// Amiga 500: RC low-pass at about 4.4 kHz, and high-pass at about 5 Hz
+    A500,
+    /// Amiga 1200: RC low-pass at about 34 kHz, and high-pass at about 5 Hz
+    A1200,
+    /// No filtering
+    #[default]
+    Unfiltered,
+}
+
+impl AmigaModel {
+    /// Cutoff frequencies of the fixed low-pass and high-pass filters
+    const fn cutoffs(self) -> Option<(f32, f32)> {
+        match self {
+            // 360 ohm, 0.1 uF; 1390 ohm, 22 uF
+            Self::A500 => Some((4420.97, 5.2)),
+            // 680 ohm, 6.8 nF; 1360 ohm, 22 uF
+            Self::A1200 => Some((34_419.32, 5.32)),
+            Self::Unfiltered => None,
+        }
+    }
+}
+
+/// Cutoff of the 2 pole Butterworth "LED" filter
+const LED_CUTOFF: f32 = 3090.53;
+
+/// The analog filters between Paula and the audio output, for both stereo channels
+#[derive(Debug, Clone)]
+pub(crate) struct OutputFilter {
+    led: bool,
+    fixed: Option<(OnePole, OnePole)>,
+    led_coefs: Biquad,
+    channels: [ChannelState; 2],
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ChannelState {
+    low_pass: f32,
+    high_pass: f32,
+    led: [f32; 4],
+}
+
+/// 1 pole RC filter, `y += a * (x - y)`
+#[derive(Debug, Clone, Copy)]
+struct OnePole {
+    a: f32,
+}
+
+impl OnePole {
+    fn new(cutoff: f32, rate: f32) -> Self {
+        Self {
+            a: 1.0 - (-2.0 * PI * cutoff / rate).exp(),
+        }
+    }
+}
+
+/// Biquad coefficients, normalized so `a0` is 1
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+}
+
+impl Biquad {
+    /// Butterworth low-pass, using the bilinear transform
+    fn butterworth_low_pass(cutoff: f32, rate: f32) -> Self {
+        // Keep the prewarped cutoff below Nyquist for low output rates
+        let cutoff = cutoff.min(rate * 0.45);
+        let k = (PI * cutoff / rate).tan();
+        let norm = 1.0 / k.mul_add(k, SQRT_2.mul_add(k, 1.0));
+        let b0 = k * k * norm;
+        Self {
+            b: [b0, 2.0 * b0, b0],
+            a: [
+                2.0 * k.mul_add(k, -1.0) * norm,
+                k.mul_add(k, SQRT_2.mul_add(-k, 1.0)) * norm,
+            ],
+        }
+    }
+}
+
+impl OutputFilter {
+    pub(crate) fn new(model: AmigaModel, led: bool, rate: u32) -> Self {
+        let rate = f64::from(rate) as f32;
+        Self {
+            led,
+            fixed: model
+                .cutoffs()
+                .map(|(lp, hp)| (OnePole::new(lp, rate), OnePole::new(hp, rate))),
+            led_coefs: Biquad::butterworth_low_pass(LED_CUTOFF, rate),
+            channels: [ChannelState::default(); 2],
+        }
+    }
+    /// Whether the filter does anything
+    pub(crate) const fn is_active(&self) -> bool {
+        self.fixed.is_some() || self.led
+    }
+    pub(crate) const fn led(&self) -> bool {
+        self.led
+    }
+    pub(crate) const fn set_led(&mut self, on: bool) {
+        self.led = on;
+    }
+    /// Forget the past samples
+    pub(crate) fn reset(&mut self) {
+        self.channels = [ChannelState::default(); 2];
+    }
+    /// Filter the next sample of channel `ch`
+    pub(crate) fn process(&mut self, ch: usize, x: f32) -> f32 {
+        let state = &mut self.channels[ch];
+        let mut y = x;
+        if let Some((lp, hp)) = self.fixed {
+            state.low_pass = lp.a.mul_add(y - state.low_pass, state.low_pass);
+            y = state.low_pass;
+            state.high_pass = hp.a.mul_add(y - state.high_pass, state.high_pass);
+            y -= state.high_pass;
+        }
+        if self.led {
+            let Biquad { b, a } = self.led_coefs;
+            let [x1, x2, y1, y2] = state.led;
+            let out = b[2].mul_add(x2, b[1].mul_add(x1, b[0] * y)) - a[1].mul_add(y2, a[0] * y1);
+            state.led = [y, x1, out, y1];
+            y = out;
+        }
+        y
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{MAX_CHANNELS, MAX_SONGS, MdatLoadError, TEXT_ROW_LEN, TEXT_ROWS};
+use {
+    crate::{MAX_CHANNELS, MAX_SONGS, MdatLoadError, TEXT_ROW_LEN, TEXT_ROWS},
+    std::io::{Read, Seek, SeekFrom},
+};
 
 #[derive(Clone, Copy, Debug)]
 pub struct Header {
@@ -9,10 +12,14 @@ pub struct Header {
     pub track_start: usize,
     pub patt_start: usize,
     pub macro_start: usize,
+    /// Filled in by `load_mdat`
+    pub macro_count: usize,
+    /// Filled in by `load_mdat`
+    pub pattern_count: usize,
 }
 
 impl Header {
-    pub fn from_reader<R: std::io::Read>(reader: &mut R) -> Result<Self, MdatLoadError> {
+    pub fn from_reader<R: Read + ?Sized>(reader: &mut R) -> Result<Self, MdatLoadError> {
         let mut img: HeaderImage = bytemuck::zeroed();
         reader.read_exact(bytemuck::bytes_of_mut(&mut img))?;
         if !(&img.magic[0..9] == b"TFMX-SONG"
@@ -45,6 +52,8 @@ impl Header {
             track_start,
             patt_start,
             macro_start,
+            macro_count: 0,
+            pattern_count: 0,
         })
     }
     /// Return the rows of text that are valid UTF-8 and aren't empty
@@ -60,6 +69,9 @@ impl Header {
     }
 }
 
+/// Size of the .mdat header, which precedes the data loaded into the edit buffer
+pub const HEADER_SIZE: usize = size_of::<HeaderImage>();
+
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct HeaderImage {
@@ -75,3 +87,69 @@ struct HeaderImage {
     macrostart: u32,
     _pad2: [u8; 36],
 }
+
+/// Header of a single-file TFMX module, which holds both the .mdat and the .smpl data
+#[derive(Clone, Copy, Debug)]
+pub struct Tfhd {
+    /// Offset of the .mdat data from the start of the file
+    pub header_size: u32,
+    pub variant: FormatVariant,
+    pub mdat_size: u32,
+    pub smpl_size: u32,
+}
+
+impl Tfhd {
+    /// Try to read a TFHD header, rewinding the reader to where it was if there isn't one
+    pub fn from_reader<R: Read + Seek + ?Sized>(reader: &mut R) -> std::io::Result<Option<Self>> {
+        let start = reader.stream_position()?;
+        let mut img: TfhdImage = bytemuck::zeroed();
+        let n = crate::read_up_to(reader, bytemuck::bytes_of_mut(&mut img))?;
+        reader.seek(SeekFrom::Start(start))?;
+        if n < size_of::<TfhdImage>() || &img.magic != b"TFHD" {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            header_size: u32::from_be_bytes(img.header_size),
+            variant: FormatVariant::from_type_byte(img.type_),
+            mdat_size: u32::from_be_bytes(img.mdat_size),
+            smpl_size: u32::from_be_bytes(img.smpl_size),
+        }))
+    }
+}
+
+/// Format variant declared by a TFHD header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatVariant {
+    /// The header doesn't say
+    Unchecked,
+    /// TFMX 1.5
+    Tfmx15,
+    /// TFMX Professional
+    TfmxPro,
+    /// TFMX 7 voices
+    Tfmx7V,
+}
+
+impl FormatVariant {
+    const fn from_type_byte(byte: u8) -> Self {
+        match byte & 0b11 {
+            1 => Self::Tfmx15,
+            2 => Self::TfmxPro,
+            3 => Self::Tfmx7V,
+            _ => Self::Unchecked,
+        }
+    }
+}
+
+/// The fields are unaligned, so they are stored as byte arrays
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TfhdImage {
+    magic: [u8; 4],
+    header_size: [u8; 4],
+    type_: u8,
+    version: u8,
+    mdat_size: [u8; 4],
+    smpl_size: [u8; 4],
+    _pad: [u8; 2],
+}
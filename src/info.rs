@@ -0,0 +1,195 @@
+use {
+    crate::{
+        MAX_SONGS, MdatLoadError, ReadSeek, SongIdx, TfmxCtx, header::FormatVariant,
+        header::Header, load_module, song,
+    },
+    std::io::{Read, Seek},
+};
+
+/// How many ticks to simulate when looking for the subsongs of a module.
+///
+/// Around a minute of playback at the default timing.
+const DISCOVERY_TICKS: usize = 3000;
+
+/// Song table slots, classified by [`discover_subsongs`]
+pub(crate) type SubsongKinds = [SubsongKind; MAX_SONGS as usize];
+
+/// Information about a loaded module, available without playing it
+#[derive(Debug, Clone)]
+pub struct ModuleInfo {
+    /// The rows of the header text that aren't empty
+    pub text: Vec<String>,
+    /// The subsongs that are valid
+    pub subsongs: Vec<SubsongInfo>,
+    /// Number of macros
+    pub macro_count: usize,
+    /// Number of patterns
+    pub pattern_count: usize,
+    /// Whether the module switches to 7 voice mode
+    pub seven_voice: bool,
+    /// Format variant declared by the header of a single-file module
+    pub variant: FormatVariant,
+}
+
+/// What a slot of the song table holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubsongKind {
+    /// A subsong that plays something
+    Valid,
+    /// The slot is unused, or the song doesn't play anything
+    Empty,
+    /// The slot plays the same thing as the earlier slot with this index
+    Duplicate(SongIdx),
+}
+
+/// Information about a subsong, taken from the song table of the module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubsongInfo {
+    /// Index of the subsong
+    pub index: SongIdx,
+    /// First step of the track table that belongs to this song
+    pub first_step: u16,
+    /// Last step of the track table that belongs to this song
+    pub last_step: u16,
+    /// Speed of the song.
+    ///
+    /// Values below `0x10` are the number of ticks per sequencer step, while
+    /// values from `0x10` set the timer rate like a BPM value.
+    pub tempo: u16,
+}
+
+impl ModuleInfo {
+    pub(crate) fn new(header: &Header, tfmx: &TfmxCtx, kinds: &SubsongKinds) -> Self {
+        Self {
+            text: header
+                .text_rows()
+                .map(|row| row.trim_end_matches('\0').trim_end().to_owned())
+                .collect(),
+            subsongs: (0..MAX_SONGS)
+                .filter(|&idx| kinds[usize::from(idx)] == SubsongKind::Valid)
+                .map(|idx| SubsongInfo::new(header, idx))
+                .collect(),
+            macro_count: header.macro_count,
+            pattern_count: header.pattern_count,
+            seven_voice: tfmx.variant == FormatVariant::Tfmx7V || uses_7_voices(header, tfmx),
+            variant: tfmx.variant,
+        }
+    }
+    /// Read information about a module from .mdat data (or a single-file module),
+    /// without loading any sample data.
+    ///
+    /// # Errors
+    ///
+    /// Errors on .mdat loading error
+    pub fn from_reader(mut reader: impl Read + Seek) -> Result<Self, MdatLoadError> {
+        let mut tfmx = TfmxCtx::new(44_100);
+        let reader: &mut dyn ReadSeek = &mut reader;
+        let (header, _) = load_module(reader, &mut tfmx)?;
+        Ok(Self::new(
+            &header,
+            &tfmx,
+            &discover_subsongs(&header, &tfmx),
+        ))
+    }
+}
+
+impl SubsongInfo {
+    pub(crate) fn new(header: &Header, idx: SongIdx) -> Self {
+        let idx_us = usize::from(idx);
+        Self {
+            index: idx,
+            first_step: header.song_starts[idx_us],
+            last_step: header.song_ends[idx_us],
+            tempo: header.song_tempos[idx_us],
+        }
+    }
+}
+
+/// Classify the slots of the song table by simulating the start of each song.
+///
+/// `clean_tfmx` is the context as it is after loading the module.
+pub(crate) fn discover_subsongs(header: &Header, clean_tfmx: &TfmxCtx) -> SubsongKinds {
+    let mut kinds = [SubsongKind::Empty; MAX_SONGS as usize];
+    let mut seen: Vec<(SongIdx, Vec<StepState>)> = Vec::new();
+    for idx in 0..MAX_SONGS {
+        if !song_table_entry_valid(header, clean_tfmx, idx) {
+            continue;
+        }
+        let Some(steps) = simulate_start(header, clean_tfmx, idx) else {
+            continue;
+        };
+        kinds[usize::from(idx)] =
+            if let Some(&(orig, _)) = seen.iter().find(|(_, other)| *other == steps) {
+                SubsongKind::Duplicate(orig)
+            } else {
+                seen.push((idx, steps));
+                SubsongKind::Valid
+            };
+    }
+    log::debug!("Subsongs: {kinds:?}");
+    kinds
+}
+
+/// Sequencer state at a track step, for telling apart songs
+#[derive(PartialEq, Eq)]
+struct StepState {
+    pos: u16,
+    prescale: u16,
+    e_clocks: u32,
+}
+
+impl StepState {
+    const fn new(tfmx: &TfmxCtx) -> Self {
+        Self {
+            pos: tfmx.pdblk.curr_pos,
+            prescale: tfmx.pdblk.prescale,
+            e_clocks: tfmx.e_clocks,
+        }
+    }
+}
+
+/// Play the start of a song without mixing, recording the track steps it goes through.
+///
+/// Returns `None` if no voice was ever turned on.
+fn simulate_start(header: &Header, clean_tfmx: &TfmxCtx, idx: SongIdx) -> Option<Vec<StepState>> {
+    let mut tfmx = clean_tfmx.start_copy(header, idx, 1);
+    let mut steps = vec![StepState::new(&tfmx)];
+    let mut audible = false;
+    for _ in 0..DISCOVERY_TICKS {
+        if !tfmx.mdb.player_enable {
+            break;
+        }
+        song::tfmx_irq_in(header, &mut tfmx);
+        // A new track step was fetched during this tick
+        if tfmx.jiffies == 0 {
+            steps.push(StepState::new(&tfmx));
+        }
+        audible |= tfmx.hdb.iter().any(|hw| hw.mode & 1 != 0 && hw.vol != 0);
+    }
+    audible.then_some(steps)
+}
+
+/// Unused entries of the song table are all zeroes, or otherwise nonsensical
+fn song_table_entry_valid(header: &Header, tfmx: &TfmxCtx, idx: SongIdx) -> bool {
+    let idx_us = usize::from(idx);
+    let (start, end, tempo) = (
+        header.song_starts[idx_us],
+        header.song_ends[idx_us],
+        header.song_tempos[idx_us],
+    );
+    let fst_pat = tfmx.editbuf[header.patt_start] as usize;
+    let n_steps = fst_pat.saturating_sub(header.track_start) / 4;
+    start <= end && usize::from(end) < n_steps && (idx == 0 || start != 0 || end != 0 || tempo != 0)
+}
+
+/// Whether the track table contains a command that switches to 7 voice mode
+fn uses_7_voices(header: &Header, tfmx: &TfmxCtx) -> bool {
+    let fst_pat = tfmx.editbuf[header.patt_start] as usize;
+    let Some(tracks) = tfmx.editbuf.get(header.track_start..fst_pat) else {
+        return false;
+    };
+    tracks.chunks_exact(4).any(|step| {
+        let l: &[u16] = bytemuck::cast_slice(step);
+        l[0] == 0xeffe && l[1] == 3 && l[3] & 0x8000 == 0
+    })
+}
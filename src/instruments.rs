@@ -0,0 +1,287 @@
+use {
+    crate::{
+        EditBuf,
+        disasm::{self, MacroCommand},
+        header::Header,
+    },
+    std::{
+        collections::BTreeMap,
+        io::{self, Write},
+        ops::Range,
+    },
+};
+
+/// Rate the samples are written with: the Amiga playing them at period 428, which is
+/// the usual period of a C
+pub const INSTRUMENT_RATE: u32 = 8287;
+
+/// A region of the sample data that macros play, found by [`crate::TfmxPlayer::instruments`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instrument {
+    /// Byte range of the sample data that plays when the sound starts
+    pub range: Range<usize>,
+    /// Byte range that repeats after that, if the sound loops.
+    ///
+    /// Like on the Amiga, it doesn't have to be inside [`Self::range`].
+    pub loop_range: Option<Range<usize>>,
+    /// Macros that play the region
+    pub macros: Vec<u8>,
+}
+
+/// File format to export an [`Instrument`] as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InstrumentFormat {
+    /// 8 bit WAV, with a `smpl` chunk for the loop
+    #[default]
+    Wav,
+    /// IFF 8SVX, with the loop as the repeat part
+    Iff8svx,
+}
+
+impl InstrumentFormat {
+    /// File extension of the format
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Iff8svx => "8svx",
+        }
+    }
+}
+
+impl Instrument {
+    /// The samples as they play: the start, then the loop if it isn't at the end of the
+    /// start already. Returns them with the offset the loop starts at.
+    fn body(&self, samples: &[i8]) -> Option<(Vec<i8>, Option<usize>)> {
+        let mut body = samples.get(self.range.clone())?.to_vec();
+        let loop_start = match &self.loop_range {
+            Some(lp) if lp.start >= self.range.start && lp.end == self.range.end => {
+                Some(lp.start - self.range.start)
+            }
+            Some(lp) => {
+                body.extend_from_slice(samples.get(lp.clone())?);
+                Some(self.range.len())
+            }
+            None => None,
+        };
+        Some((body, loop_start))
+    }
+}
+
+/// Sample registers of a voice, as set by a macro
+#[derive(Default)]
+struct Registers {
+    addr: u32,
+    /// Length in words
+    len: u16,
+}
+
+impl Registers {
+    fn range(&self) -> Range<usize> {
+        let start = self.addr as usize;
+        start..start + usize::from(self.len) * 2
+    }
+}
+
+/// A sound that a macro starts, with the registers it left for the loop
+struct Sound {
+    range: Range<usize>,
+    loop_range: Range<usize>,
+}
+
+/// Bounds of a sound and its loop, as ranges aren't `Ord`
+type RegionKey = ((usize, usize), Option<(usize, usize)>);
+
+/// Walk every macro of the module and collect the sample regions they set up, leaving
+/// out the ones that aren't inside the `smpl_len` bytes of sample data
+pub(crate) fn analyze(header: &Header, editbuf: &EditBuf, smpl_len: usize) -> Vec<Instrument> {
+    let mut found: BTreeMap<RegionKey, Vec<u8>> = BTreeMap::new();
+    for macro_num in 0..header.macro_count {
+        let mut regs = Registers::default();
+        let mut sounds = Vec::new();
+        let mut playing: Option<Sound> = None;
+        let Some(steps) = disasm::macro_words(header, editbuf, macro_num as u8) else {
+            continue;
+        };
+        for (_, bytes) in steps {
+            match MacroCommand::decode(bytes) {
+                MacroCommand::DmaOn { .. } => {
+                    sounds.extend(playing.take());
+                    playing = Some(Sound {
+                        range: regs.range(),
+                        loop_range: regs.range(),
+                    });
+                    continue;
+                }
+                MacroCommand::SetBegin { addr } => regs.addr = addr,
+                MacroCommand::SetLen { len } => regs.len = len,
+                MacroCommand::AddBegin { offset, .. } => {
+                    regs.addr = regs.addr.wrapping_add(i32::from(offset) as u32);
+                }
+                MacroCommand::AddLen { offset } => regs.len = regs.len.wrapping_add(offset as u16),
+                MacroCommand::SampleLoop { offset } => {
+                    regs.addr = regs.addr.wrapping_add(u32::from(offset) & 0xfffe);
+                    regs.len = regs.len.wrapping_sub(offset >> 1);
+                }
+                MacroCommand::OneShot => regs = Registers { addr: 0, len: 1 },
+                MacroCommand::Stop => break,
+                _ => continue,
+            }
+            // Registers set while a sound plays take effect when it loops
+            if let Some(sound) = &mut playing {
+                sound.loop_range = regs.range();
+            }
+        }
+        sounds.extend(playing);
+        for sound in sounds {
+            if sound.range.is_empty() || sound.range.end > smpl_len {
+                continue;
+            }
+            // A loop of a single word is how sounds play once
+            let loop_range = Some(sound.loop_range).filter(|lp| lp.len() > 2 && lp.end <= smpl_len);
+            let macros = found
+                .entry((
+                    (sound.range.start, sound.range.end),
+                    loop_range.map(|lp| (lp.start, lp.end)),
+                ))
+                .or_default();
+            if macros.last() != Some(&(macro_num as u8)) {
+                macros.push(macro_num as u8);
+            }
+        }
+    }
+    found
+        .into_iter()
+        .map(|((range, loop_range), macros)| Instrument {
+            range: range.0..range.1,
+            loop_range: loop_range.map(|lp| lp.0..lp.1),
+            macros,
+        })
+        .collect()
+}
+
+/// Write `instrument`, taking its data from `samples`
+pub(crate) fn write(
+    instrument: &Instrument,
+    samples: &[i8],
+    format: InstrumentFormat,
+    out: impl Write,
+) -> io::Result<()> {
+    let (body, loop_start) = instrument.body(samples).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Instrument outside of the sample data",
+        )
+    })?;
+    let name = instrument
+        .macros
+        .iter()
+        .map(|num| format!("{num:02X}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let name = format!("Macro {name}");
+    match format {
+        InstrumentFormat::Wav => write_wav(&body, loop_start, &name, out),
+        InstrumentFormat::Iff8svx => write_8svx(&body, loop_start, &name, out),
+    }
+}
+
+fn write_wav(
+    body: &[i8],
+    loop_start: Option<usize>,
+    name: &str,
+    mut out: impl Write,
+) -> io::Result<()> {
+    let mut chunks = Vec::new();
+    chunks.extend_from_slice(b"fmt ");
+    chunks.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, mono
+    chunks.extend_from_slice(&1u16.to_le_bytes());
+    chunks.extend_from_slice(&1u16.to_le_bytes());
+    chunks.extend_from_slice(&INSTRUMENT_RATE.to_le_bytes());
+    chunks.extend_from_slice(&INSTRUMENT_RATE.to_le_bytes());
+    chunks.extend_from_slice(&1u16.to_le_bytes());
+    chunks.extend_from_slice(&8u16.to_le_bytes());
+    if let Some(loop_start) = loop_start {
+        let fields = [
+            // Manufacturer, product, sample period in ns, MIDI unity note, pitch fraction,
+            // SMPTE format and offset, loop count, sampler data
+            0,
+            0,
+            1_000_000_000 / INSTRUMENT_RATE,
+            60,
+            0,
+            0,
+            0,
+            1,
+            0,
+            // Cue point ID, forward loop, first and last sample, fraction, play forever
+            0,
+            0,
+            loop_start as u32,
+            body.len() as u32 - 1,
+            0,
+            0,
+        ];
+        chunks.extend_from_slice(b"smpl");
+        chunks.extend_from_slice(&(fields.len() as u32 * 4).to_le_bytes());
+        chunks.extend(fields.iter().flat_map(|field| field.to_le_bytes()));
+    }
+    let mut text = name.as_bytes().to_vec();
+    text.push(0);
+    if text.len() % 2 == 1 {
+        text.push(0);
+    }
+    chunks.extend_from_slice(b"LIST");
+    chunks.extend_from_slice(&(text.len() as u32 + 12).to_le_bytes());
+    chunks.extend_from_slice(b"INFOINAM");
+    chunks.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    chunks.extend_from_slice(&text);
+    chunks.extend_from_slice(b"data");
+    chunks.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    // 8 bit WAV samples are unsigned
+    chunks.extend(body.iter().map(|&sample| sample as u8 ^ 0x80));
+    if body.len() % 2 == 1 {
+        chunks.push(0);
+    }
+    out.write_all(b"RIFF")?;
+    out.write_all(&(chunks.len() as u32 + 4).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+    out.write_all(&chunks)
+}
+
+fn write_8svx(
+    body: &[i8],
+    loop_start: Option<usize>,
+    name: &str,
+    mut out: impl Write,
+) -> io::Result<()> {
+    let one_shot = loop_start.unwrap_or(body.len());
+    let mut chunks = Vec::new();
+    chunks.extend_from_slice(b"VHDR");
+    chunks.extend_from_slice(&20u32.to_be_bytes());
+    chunks.extend_from_slice(&(one_shot as u32).to_be_bytes());
+    chunks.extend_from_slice(&((body.len() - one_shot) as u32).to_be_bytes());
+    // Samples per high cycle, unknown
+    chunks.extend_from_slice(&0u32.to_be_bytes());
+    chunks.extend_from_slice(&(INSTRUMENT_RATE as u16).to_be_bytes());
+    // One octave, no compression, full volume
+    chunks.extend_from_slice(&[1, 0]);
+    chunks.extend_from_slice(&0x1_0000u32.to_be_bytes());
+    chunks.extend_from_slice(b"NAME");
+    chunks.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    chunks.extend_from_slice(name.as_bytes());
+    if name.len() % 2 == 1 {
+        chunks.push(0);
+    }
+    chunks.extend_from_slice(b"BODY");
+    chunks.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    chunks.extend(body.iter().map(|&sample| sample as u8));
+    if body.len() % 2 == 1 {
+        chunks.push(0);
+    }
+    out.write_all(b"FORM")?;
+    out.write_all(&(chunks.len() as u32 + 4).to_be_bytes())?;
+    out.write_all(b"8SVX")?;
+    out.write_all(&chunks)
+}
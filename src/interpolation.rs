@@ -0,0 +1,82 @@
+use std::{f64::consts::PI, sync::LazyLock};
+
+/// How the mixer calculates the sample values between the points of the sample data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Hold each point until the next one, like the Amiga hardware does
+    Nearest,
+    /// Draw a straight line between neighbouring points
+    #[default]
+    Linear,
+    /// Cubic Hermite spline through the 4 surrounding points
+    Cubic,
+    /// Windowed sinc filter, which also band-limits high notes to avoid aliasing
+    Sinc,
+}
+
+impl Interpolation {
+    /// The value at `frac` (0..1) of the way from point 0 to point 1, for a voice that
+    /// advances `step` points per output sample.
+    ///
+    /// Only for the floating point modes, [`Self::Cubic`] and [`Self::Sinc`].
+    pub(crate) fn filter(self, point: impl Fn(isize) -> f32, frac: f32, step: f32) -> f32 {
+        match self {
+            Self::Cubic => hermite([point(-1), point(0), point(1), point(2)], frac),
+            Self::Sinc => windowed_sinc(point, frac, step),
+            Self::Nearest | Self::Linear => point(0),
+        }
+    }
+}
+
+fn hermite([p0, p1, p2, p3]: [f32; 4], t: f32) -> f32 {
+    let c1 = 0.5 * (p2 - p0);
+    let c2 = 0.5f32.mul_add(-p3, 2.0f32.mul_add(p2, 2.5f32.mul_add(-p1, p0)));
+    let c3 = 1.5f32.mul_add(p1 - p2, 0.5 * (p3 - p0));
+    c3.mul_add(t, c2).mul_add(t, c1).mul_add(t, p1)
+}
+
+/// Half the width of the sinc kernel, in points
+const ZERO_CROSSINGS: u16 = 8;
+/// Table entries per point
+const TABLE_RES: u16 = 256;
+/// How far the kernel gets stretched at most when band-limiting high notes
+const MAX_STRETCH: f32 = 4.0;
+
+/// One side of the Blackman windowed sinc kernel
+static SINC_TABLE: LazyLock<Vec<f32>> = LazyLock::new(|| {
+    (0..=u32::from(ZERO_CROSSINGS) * u32::from(TABLE_RES))
+        .map(|i| {
+            let x = f64::from(i) / f64::from(TABLE_RES);
+            let sinc = if i == 0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let w = x / f64::from(ZERO_CROSSINGS);
+            let window =
+                0.08f64.mul_add((2.0 * PI * w).cos(), 0.5f64.mul_add((PI * w).cos(), 0.42));
+            (sinc * window) as f32
+        })
+        .collect()
+});
+
+fn kernel(x: f32) -> f32 {
+    let idx = (x.abs() * f32::from(TABLE_RES)) as usize;
+    SINC_TABLE.get(idx).copied().unwrap_or(0.0)
+}
+
+fn windowed_sinc(point: impl Fn(isize) -> f32, frac: f32, step: f32) -> f32 {
+    // Above one point per output sample, lower the cutoff to the output rate
+    let stretch = step.clamp(1.0, MAX_STRETCH);
+    let half_width = f32::from(ZERO_CROSSINGS) * stretch;
+    let first = (frac - half_width).floor() as isize + 1;
+    let last = (frac + half_width).ceil() as isize - 1;
+    let mut sum = 0.0;
+    let mut weights = 0.0;
+    for k in first..=last {
+        let w = kernel((f32::from(k as i16) - frac) / stretch);
+        sum += w * point(k);
+        weights += w;
+    }
+    if weights == 0.0 { 0.0 } else { sum / weights }
+}
@@ -23,19 +23,60 @@
     clippy::cognitive_complexity
 )]
 
+mod asm;
+mod disasm;
+mod duration;
+mod events;
+mod filter;
 mod header;
+mod info;
+mod instruments;
+mod interpolation;
+mod limiter;
+mod looping;
+mod meters;
+mod midi;
+mod module;
 mod rendering;
+mod sample;
 mod song;
+mod wav;
 
 use std::{
     fs::File,
-    io::{Read, Seek, SeekFrom},
+    io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write},
     ops::ControlFlow,
-    path::Path,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
 };
 
-use header::Header;
-use rendering::{AudioCtx, present_output, try_to_makeblock};
+pub use {
+    asm::{AssembleError, AssembleErrorKind},
+    disasm::{
+        MacroCommand, MacroStep, PatternCommand, PatternStep, TrackCommand, TrackStep, TrackVoice,
+    },
+    duration::SongDuration,
+    events::{Event, EventKind},
+    filter::AmigaModel,
+    header::FormatVariant,
+    info::{ModuleInfo, SubsongInfo, SubsongKind},
+    instruments::{INSTRUMENT_RATE, Instrument, InstrumentFormat},
+    interpolation::Interpolation,
+    meters::VoiceMeter,
+    module::{Macro, Module, Pattern, SongEntry},
+    sample::{ChannelLayout, Sample},
+    wav::{Stems, WavOptions},
+};
+
+use events::EventQueue;
+use filter::OutputFilter;
+use header::{Header, Tfhd};
+use info::SubsongKinds;
+use limiter::Limiter;
+use looping::LoopTracker;
+use meters::MeterTap;
+use rendering::{AudioCtx, CHUNK_LEN};
 use song::{Cdb, Hdb, Idb, Mdb, Pdblk};
 
 const TEXT_ROW_LEN: u8 = 40;
@@ -49,10 +90,11 @@ struct TfmxCtx {
     oops_up_hack: bool,
     single_file: bool,
     ntfhd_offset: u32,
+    variant: FormatVariant,
     out_rate: u32,
     editbuf: Box<EditBuf>,
     gemx: bool,
-    loops: i32,
+    loop_tracker: LoopTracker,
     hdb: HdbArr,
     mdb: Mdb,
     cdb: CdbArr,
@@ -61,6 +103,7 @@ struct TfmxCtx {
     jiffies: i32,
     multimode: bool,
     e_clocks: u32,
+    events: EventQueue,
 }
 
 type CdbArr = [Cdb; 16];
@@ -76,8 +119,9 @@ impl TfmxCtx {
             oops_up_hack: false,
             single_file: false,
             ntfhd_offset: 0,
+            variant: FormatVariant::Unchecked,
             gemx: false,
-            loops: 0,
+            loop_tracker: LoopTracker::default(),
             hdb: [Hdb::default(); MAX_CHANNELS as usize],
             mdb: Mdb::default(),
             cdb: [Cdb::default(); 16],
@@ -86,6 +130,7 @@ impl TfmxCtx {
             jiffies: 0,
             multimode: false,
             e_clocks: 14318,
+            events: EventQueue::default(),
         }
     }
 
@@ -98,90 +143,48 @@ impl TfmxCtx {
             song::channel_off(ch_idx & 0xF, &mut self.cdb, &mut self.hdb);
         }
     }
+
+    /// A copy of this (clean) context with song `idx` started, which ends after
+    /// `max_passes` passes
+    fn start_copy(&self, header: &Header, idx: SongIdx, max_passes: u32) -> Self {
+        let mut tfmx = self.clone();
+        tfmx.loop_tracker = LoopTracker::new(max_passes);
+        tfmx.init();
+        song::start_song(idx, 0, header, &mut tfmx);
+        tfmx
+    }
 }
 
-fn play_loop(player: &mut TfmxPlayer, mut handler: impl NewDataFn) {
-    'do_over: loop {
+fn play_loop<S: Sample>(player: &mut TfmxPlayer, mut handler: impl NewDataFn<S>) {
+    let frames = CHUNK_LEN / 2;
+    let mut buf = vec![S::default(); frames * player.layout.channels()];
+    loop {
         if player.song_idx >= MAX_SONGS {
             log::info!("Reached maximum number of songs, ending play loop.");
             break;
         }
-        let mut audio = AudioCtx::new();
-        player.tfmx = player.clean_tfmx.clone();
-        player.tfmx.init();
-        song::start_song(player.song_idx, 0, &player.header, &mut player.tfmx);
-        log::info!("Playing song {}", player.song_idx);
-        while try_to_makeblock(
-            &player.header,
-            &mut audio,
-            &mut player.tfmx,
-            &player.sample_buf,
-            player.ch_on,
-        ) != Some(0)
-        {
-            log::trace!("Making some blocks...");
-        }
-        while try_to_makeblock(
-            &player.header,
-            &mut audio,
-            &mut player.tfmx,
-            &player.sample_buf,
-            player.ch_on,
-        )
-        .is_some()
-        {
-            match present_output(player, &mut audio, &mut handler) {
+        let written = player.render(&mut buf);
+        let song_ended = written < buf.len();
+        if written != 0 {
+            match handler(&buf[..written], player) {
                 ControlFlow::Continue(cmd) => {
-                    if let Some(cmd) = cmd {
-                        match cmd {
-                            PlayerCmd::Prev => {
-                                player.song_idx = player.song_idx.saturating_sub(1);
-                                continue 'do_over;
-                            }
-                            PlayerCmd::Next => {
-                                player.song_idx += 1;
-                                continue 'do_over;
-                            }
-                            PlayerCmd::RestartSong => {
-                                continue 'do_over;
-                            }
-                            PlayerCmd::ToggleBlend => {
-                                audio.toggle_blend();
-                                log::info!("Stereo blend {}", audio.is_blend_on().on_off());
-                            }
-                            PlayerCmd::ToggleCh(ch_idx) => {
-                                match player.ch_on.get_mut(ch_idx as usize) {
-                                    Some(ch) => {
-                                        *ch ^= true;
-                                        let on_off = |b| if b { "X" } else { "_" };
-                                        log::info!(
-                                            "Channel status: {:?}",
-                                            player.ch_on.map(on_off)
-                                        );
-                                    }
-                                    None => {
-                                        log::warn!("No such channel: {ch_idx}");
-                                    }
-                                }
-                            }
-                            PlayerCmd::ToggleLoopCurrentSong => {
-                                player.loop_current_song ^= true;
-                                log::info!(
-                                    "Loop current song {}",
-                                    player.loop_current_song.on_off()
-                                );
-                            }
-                        }
+                    if let Some(cmd) = cmd
+                        && player.exec_cmd(cmd)
+                    {
+                        continue;
                     }
                 }
                 ControlFlow::Break(()) => {
                     log::info!("Stopping playback on request.");
-                    break 'do_over;
+                    break;
                 }
             }
         }
-        if !player.loop_current_song {
-            player.song_idx += 1;
+        if song_ended {
+            if !player.loop_current_song {
+                player.song_idx = player.next_valid_song(player.song_idx);
+            }
+            player.restart_song();
         }
     }
 }
@@ -198,6 +201,7 @@ impl BoolExt for bool {
 
 /// .mdat loading error
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum MdatLoadError {
     /// File doesn't proclaim itself as a TFMX file
     #[error("Not a valid TFMX file (magic mismatch)")]
@@ -214,61 +218,174 @@ pub enum MdatLoadError {
     /// Some kind of preprocessing step went wrong
     #[error("Error occured during preprocessing")]
     PreprocessError,
+    /// Patterns, macros or tables of the module start at the same place, or run into
+    /// each other
+    #[error("Module data overlaps")]
+    OverlappingData,
+}
+
+/// Error when trying to play a sound effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum SfxError {
+    /// No song is playing, so there is no player to run the effect
+    #[error("No song is playing")]
+    NotPlaying,
+    /// The channel doesn't exist in the current song
+    #[error("No such channel: {0}")]
+    NoSuchChannel(u8),
+    /// The module doesn't have the macro
+    #[error("No such macro: {0}")]
+    NoSuchMacro(u8),
+    /// Notes go from 0 to 63
+    #[error("Note out of range: {0}")]
+    InvalidNote(u8),
+    /// Volumes go from 0 to 15
+    #[error("Volume out of range: {0}")]
+    InvalidVolume(u8),
+    /// The channel is locked, or has a sound effect waiting to start, with a higher
+    /// priority
+    #[error("Channel is locked with a higher priority")]
+    Locked,
 }
 
-fn load_mdat(mdat_path: &Path, tfmx: &mut TfmxCtx) -> Result<Header, MdatLoadError> {
+/// Load the .mdat data into the edit buffer.
+///
+/// For single-file modules, `mdat_len` limits how much is read, so the sample data
+/// following the .mdat doesn't end up in the edit buffer.
+fn load_mdat<R: Read + Seek + ?Sized>(
+    reader: &mut R,
+    tfmx: &mut TfmxCtx,
+    mdat_len: Option<u32>,
+) -> Result<Header, MdatLoadError> {
     let &mut TfmxCtx {
         single_file,
         ntfhd_offset,
         ref mut editbuf,
         ..
     } = tfmx;
-    let mut f = File::open(mdat_path)?;
     if single_file {
-        f.seek(SeekFrom::Current(i64::from(ntfhd_offset)))?;
+        reader.seek(SeekFrom::Current(i64::from(ntfhd_offset)))?;
     }
-    let header = Header::from_reader(&mut f)?;
-    let n = f.read(bytemuck::bytes_of_mut(&mut **editbuf))? / size_of::<u32>();
+    let mut header = Header::from_reader(reader)?;
+    // Leave room for the end marker
+    let mut last = editbuf.len() - 1;
+    if let Some(len) = mdat_len {
+        let data_len = (len as usize).saturating_sub(header::HEADER_SIZE);
+        last = last.min(data_len / size_of::<u32>());
+    }
+    let n = read_up_to(reader, bytemuck::cast_slice_mut(&mut editbuf[..last]))? / size_of::<u32>();
     editbuf[n] = u32::MAX;
     if n < 127 {
         return Err(MdatLoadError::EditBufferTooSmall { size: n });
     }
+    header.macro_count = rebase_offsets(editbuf, header.macro_start, n)?;
+    log::debug!("Counted {} macros.", header.macro_count);
+    header.pattern_count = rebase_offsets(editbuf, header.patt_start, n)?;
+    log::debug!("Counted {} patterns.", header.pattern_count);
+    let fst_pat = editbuf[header.patt_start];
+    let datapoints: &mut [u16; 32768] = bytemuck::cast_mut(&mut **editbuf);
+    for datapoint in &mut datapoints[header.track_start * 2..fst_pat as usize * 2] {
+        *datapoint = u16::from_be(*datapoint);
+    }
+    Ok(header)
+}
+
+/// Convert the file offsets in a macro or pattern offset table to edit buffer indices.
+///
+/// Returns how many valid offsets there were.
+fn rebase_offsets(editbuf: &mut EditBuf, start: usize, n: usize) -> Result<usize, MdatLoadError> {
     for i in 0..128 {
-        let z = header.macro_start + i;
+        let z = start + i;
         let y = u32::from_be(editbuf[z])
             .checked_sub(0x200)
             .ok_or(MdatLoadError::PreprocessError)?;
         if (y & 3) != 0 || (y >> 2) > n as u32 {
-            log::debug!("Counted {i} macros.");
-            break;
+            return Ok(i);
         }
         editbuf[z] = y >> 2;
     }
-    for i in 0..128 {
-        let z = header.patt_start + i;
-        let y = u32::from_be(editbuf[z])
-            .checked_sub(0x200)
-            .ok_or(MdatLoadError::PreprocessError)?;
-        if (y & 3) != 0 || (y >> 2) > n as u32 {
-            log::debug!("Counted {i} patterns.");
-            break;
+    Ok(128)
+}
+
+/// Load the .mdat part of a module, detecting whether it's a single-file module
+fn load_module<R: Read + Seek + ?Sized>(
+    reader: &mut R,
+    tfmx: &mut TfmxCtx,
+) -> Result<(Header, Option<Tfhd>), MdatLoadError> {
+    let tfhd = Tfhd::from_reader(reader)?;
+    if let Some(tfhd) = &tfhd {
+        log::info!("Single file module ({:?})", tfhd.variant);
+        tfmx.single_file = true;
+        tfmx.ntfhd_offset = tfhd.header_size;
+        tfmx.variant = tfhd.variant;
+        // 7 voice modules mix voices 4 to 7 from the start, instead of after the first
+        // tempo change. The header has no other quirks: the GEMX, Danger Freak and Oops
+        // Up hacks are for single games, which it can't tell apart.
+        tfmx.multimode = tfhd.variant == FormatVariant::Tfmx7V;
+    }
+    let header = load_mdat(reader, tfmx, tfhd.map(|tfhd| tfhd.mdat_size))?;
+    Ok((header, tfhd))
+}
+
+/// Read as many bytes as are available into `buf`, until it's full or the reader is exhausted
+fn read_up_to<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
         }
-        editbuf[z] = y >> 2;
     }
-    let fst_pat = editbuf[header.patt_start];
-    let datapoints: &mut [u16; 32768] = bytemuck::cast_mut(&mut **editbuf);
-    for datapoint in &mut datapoints[header.track_start * 2..fst_pat as usize * 2] {
-        *datapoint = u16::from_be(*datapoint);
+    Ok(filled)
+}
+
+/// Something that module data can be loaded from
+enum Source {
+    Path(String),
+    /// Data that was read into memory up front
+    Bytes(Vec<u8>),
+    /// Reading the data failed, which every build reports
+    Failed(Arc<std::io::Error>),
+}
+
+impl Source {
+    /// Read `reader` to the end, so it doesn't have to live as long as the builder
+    fn read(mut reader: impl Read) -> Self {
+        let mut buf = Vec::new();
+        match reader.read_to_end(&mut buf) {
+            Ok(_) => Self::Bytes(buf),
+            Err(e) => Self::Failed(Arc::new(e)),
+        }
+    }
+    /// The error that reading the data failed with
+    fn error(e: &Arc<std::io::Error>) -> PlayerBuildError {
+        PlayerBuildError::Read(Arc::clone(e))
     }
-    Ok(header)
 }
 
+/// Object safe combination of [`Read`] and [`Seek`]
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
 /// Used to build a [`TfmxPlayer`]
 pub struct PlayerBuilder {
-    mdat_path: String,
-    smpl_path: Option<String>,
+    mdat: Source,
+    smpl: Option<Source>,
     song_index: SongIdx,
     sample_rate: u32,
+    loop_count: u32,
+    interpolation: Interpolation,
+    amiga_model: AmigaModel,
+    led_filter: bool,
+    pans: [i8; MAX_CHANNELS as usize],
+    separation: u8,
+    master_gain: f32,
+    limiter: bool,
+    layout: ChannelLayout,
+    voice_meters: bool,
+    scope_decimation: u16,
 }
 
 /// Error when trying to build a [`TfmxPlayer`]
@@ -277,24 +394,88 @@ pub enum PlayerBuildError {
     /// I/O error
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
+    /// Reading the module from a reader failed, see [`PlayerBuilder::from_readers`].
+    /// Every build returns the same error.
+    #[error("Read error: {0}")]
+    Read(#[source] Arc<std::io::Error>),
     /// Error trying to load the .mdat file
     #[error(".mdat load error: {0}")]
     MDat(#[from] MdatLoadError),
+    /// There is no source to load the sample data from
+    #[error("No sample data source")]
+    NoSmplSource,
 }
 
 impl PlayerBuilder {
+    /// Stereo separation that sounds like the stereo blend of the original player
+    pub const BLEND_SEPARATION: u8 = rendering::BLEND_SEPARATION;
+    /// Pan positions that put each voice on the side where the player has always put it
+    pub const DEFAULT_PANS: [i8; MAX_CHANNELS as usize] = rendering::DEFAULT_PANS;
     /// Create a new [`PlayerBuilder`] with the specified .mdat path
+    ///
+    /// This can also be the path of a single-file (TFHD) module, in which case the
+    /// sample data is loaded from the same file.
     pub fn new<S: Into<String>>(mdat_path: S) -> Self {
+        Self::with_sources(Source::Path(mdat_path.into()), None)
+    }
+    /// Create a new [`PlayerBuilder`] from in-memory .mdat and .smpl data
+    pub fn from_bytes(mdat: impl Into<Vec<u8>>, smpl: impl Into<Vec<u8>>) -> Self {
+        Self::with_sources(Source::Bytes(mdat.into()), Some(Source::Bytes(smpl.into())))
+    }
+    /// Create a new [`PlayerBuilder`] that plays `module`, with the provided .smpl data
+    pub fn from_module(module: &Module, smpl: impl Into<Vec<u8>>) -> Self {
+        Self::from_bytes(module.to_mdat(), smpl)
+    }
+    /// Create a new [`PlayerBuilder`] that reads the .mdat and .smpl data from the provided readers.
+    ///
+    /// The readers are read to the end right away, so every [`Self::build`] gets the same
+    /// data. If that fails, the error is returned by [`Self::build`], as
+    /// [`PlayerBuildError::Read`].
+    pub fn from_readers(mdat: impl Read, smpl: impl Read) -> Self {
+        Self::with_sources(Source::read(mdat), Some(Source::read(smpl)))
+    }
+    /// Create a new [`PlayerBuilder`] that reads a single-file (TFHD) module from `reader`.
+    ///
+    /// The reader is read like in [`Self::from_readers`].
+    pub fn from_single_file(reader: impl Read) -> Self {
+        Self::with_sources(Source::read(reader), None)
+    }
+    const fn with_sources(mdat: Source, smpl: Option<Source>) -> Self {
         Self {
-            mdat_path: mdat_path.into(),
-            smpl_path: None,
+            mdat,
+            smpl,
             song_index: 0,
             sample_rate: 44_100,
+            loop_count: 1,
+            interpolation: Interpolation::Linear,
+            amiga_model: AmigaModel::Unfiltered,
+            led_filter: false,
+            pans: Self::DEFAULT_PANS,
+            separation: Self::BLEND_SEPARATION,
+            master_gain: 1.0,
+            limiter: false,
+            layout: ChannelLayout::Interleaved,
+            voice_meters: false,
+            scope_decimation: 0,
         }
     }
+    /// Load the sample data for a module that isn't single-file
+    fn load_smpl(&self) -> Result<Vec<u8>, PlayerBuildError> {
+        Ok(match &self.smpl {
+            Some(Source::Path(path)) => std::fs::read(path)?,
+            Some(Source::Bytes(bytes)) => bytes.clone(),
+            Some(Source::Failed(e)) => return Err(Source::error(e)),
+            None => match &self.mdat {
+                Source::Path(path) => std::fs::read(path.replace("mdat.", "smpl."))?,
+                Source::Bytes(_) | Source::Failed(_) => {
+                    return Err(PlayerBuildError::NoSmplSource);
+                }
+            },
+        })
+    }
     /// Specify a file to use as the sample file (Usually .smpl)
     pub fn smpl_file<S: Into<String>>(&mut self, path: S) -> &mut Self {
-        self.smpl_path = Some(path.into());
+        self.smpl = Some(Source::Path(path.into()));
         self
     }
     /// Which subsong to start with
@@ -307,28 +488,146 @@ impl PlayerBuilder {
         self.sample_rate = rate;
         self
     }
+    /// How many times to play a subsong through before it ends, 0 to loop forever.
+    ///
+    /// A subsong has been played through once it goes back to a track step it was
+    /// already at. Default is 1.
+    pub const fn loop_count(&mut self, count: u32) -> &mut Self {
+        self.loop_count = count;
+        self
+    }
+    /// How to calculate the sample values between the points of the sample data.
+    ///
+    /// Default is [`Interpolation::Linear`].
+    pub const fn interpolation(&mut self, interpolation: Interpolation) -> &mut Self {
+        self.interpolation = interpolation;
+        self
+    }
+    /// Which Amiga's output filters to emulate. Default is [`AmigaModel::Unfiltered`].
+    pub const fn amiga_model(&mut self, model: AmigaModel) -> &mut Self {
+        self.amiga_model = model;
+        self
+    }
+    /// Whether the "LED" low-pass filter starts out on. Default is off.
+    ///
+    /// It can be toggled during playback with [`PlayerCmd::ToggleLedFilter`].
+    pub const fn led_filter(&mut self, on: bool) -> &mut Self {
+        self.led_filter = on;
+        self
+    }
+    /// Pan position of each voice, from -100 (left) to 100 (right).
+    ///
+    /// Default is [`Self::DEFAULT_PANS`].
+    pub const fn voice_pans(&mut self, pans: [i8; MAX_CHANNELS as usize]) -> &mut Self {
+        self.pans = pans;
+        self
+    }
+    /// Stereo separation in percent, from 0 (mono) to 100 (no mixing between left and right).
+    ///
+    /// Default is [`Self::BLEND_SEPARATION`].
+    pub const fn stereo_separation(&mut self, percent: u8) -> &mut Self {
+        self.separation = percent;
+        self
+    }
+    /// Gain applied to the mix before it is converted to 16 bits. Default is 1.0.
+    ///
+    /// It can be changed during playback with [`PlayerCmd::SetMasterGain`].
+    pub const fn master_gain(&mut self, gain: f32) -> &mut Self {
+        self.master_gain = gain;
+        self
+    }
+    /// Whether to run the output through a look-ahead limiter, which turns peaks down
+    /// smoothly instead of letting them clip. Default is off.
+    ///
+    /// The limiter looks 5 milliseconds ahead, so it holds back the last 5 milliseconds
+    /// of each [`TfmxPlayer::render`] until the next one. Nothing moves in the output,
+    /// so event frames still line up with the audio.
+    pub const fn limiter(&mut self, on: bool) -> &mut Self {
+        self.limiter = on;
+        self
+    }
+    /// How the samples of the two channels are arranged in the output of
+    /// [`TfmxPlayer::render`] and [`TfmxPlayer::play`]. Default is [`ChannelLayout::Interleaved`].
+    pub const fn channel_layout(&mut self, layout: ChannelLayout) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+    /// Whether to measure each voice while mixing, see [`TfmxPlayer::voice_meters`].
+    /// Default is off.
+    pub const fn voice_meters(&mut self, on: bool) -> &mut Self {
+        self.voice_meters = on;
+        self
+    }
+    /// Keep every `n`th sample of each voice in [`VoiceMeter::scope`], for drawing
+    /// oscilloscopes. Default is 0, which keeps none.
+    ///
+    /// This only has an effect with [`Self::voice_meters`] on.
+    pub const fn scope_decimation(&mut self, n: u16) -> &mut Self {
+        self.scope_decimation = n;
+        self
+    }
     /// Build the [`TfmxPlayer`].
     ///
     /// # Errors
     ///
-    /// Errors on .mdat file loading error
+    /// Errors on .mdat file loading error, or if the sample data can't be read
     pub fn build(&mut self) -> Result<TfmxPlayer, PlayerBuildError> {
         let mut tfmx = TfmxCtx::new(self.sample_rate);
-        let header = load_mdat(self.mdat_path.as_ref(), &mut tfmx)?;
-        let sample_path = self
-            .smpl_path
-            .take()
-            .unwrap_or_else(|| self.mdat_path.replace("mdat.", "smpl."));
-        let sample_buf = std::fs::read(sample_path)?;
-        Ok(TfmxPlayer {
+        tfmx.loop_tracker = LoopTracker::new(self.loop_count);
+        let mut file;
+        let mut bytes;
+        let reader: &mut dyn ReadSeek = match &self.mdat {
+            Source::Path(path) => {
+                file = File::open(path)?;
+                &mut file
+            }
+            Source::Bytes(data) => {
+                bytes = Cursor::new(data.as_slice());
+                &mut bytes
+            }
+            Source::Failed(e) => return Err(Source::error(e)),
+        };
+        let start = reader.stream_position()?;
+        let (header, tfhd) = load_module(reader, &mut tfmx)?;
+        let sample_buf = if let Some(tfhd) = tfhd {
+            let smpl_offset = u64::from(tfhd.header_size) + u64::from(tfhd.mdat_size);
+            reader.seek(SeekFrom::Start(start + smpl_offset))?;
+            let mut buf = Vec::new();
+            reader
+                .take(u64::from(tfhd.smpl_size))
+                .read_to_end(&mut buf)?;
+            buf
+        } else {
+            self.load_smpl()?
+        };
+        let subsongs = info::discover_subsongs(&header, &tfmx);
+        let mut player = TfmxPlayer {
+            subsongs,
             clean_tfmx: tfmx.clone(),
             tfmx,
+            audio: AudioCtx::new(
+                self.interpolation,
+                OutputFilter::new(self.amiga_model, self.led_filter, self.sample_rate),
+            ),
             header,
             sample_buf: bytemuck::cast_vec(sample_buf),
             song_idx: self.song_index,
             ch_on: [true; MAX_CHANNELS as usize],
             loop_current_song: false,
-        })
+            layout: self.layout,
+        };
+        player.audio.set_pans(self.pans);
+        player.audio.set_separation(self.separation);
+        player.audio.set_master_gain(self.master_gain);
+        player
+            .audio
+            .set_limiter(self.limiter.then(|| Limiter::new(self.sample_rate)));
+        player.audio.set_meters(
+            self.voice_meters
+                .then(|| MeterTap::new(self.scope_decimation)),
+        );
+        player.restart_song();
+        Ok(player)
     }
 }
 
@@ -336,23 +635,26 @@ impl PlayerBuilder {
 pub struct TfmxPlayer {
     clean_tfmx: TfmxCtx,
     tfmx: TfmxCtx,
+    audio: AudioCtx,
     header: Header,
     sample_buf: Vec<i8>,
+    subsongs: SubsongKinds,
     song_idx: SongIdx,
     ch_on: [bool; MAX_CHANNELS as usize],
     loop_current_song: bool,
+    layout: ChannelLayout,
 }
 
 /// Max value is [`MAX_SONGS`] - 1
 type SongIdx = u8;
 
 /// Function for handling new sample data coming from the player
-pub trait NewDataFn = FnMut(&[i16], &mut TfmxPlayer) -> NewDataCtlFlow;
+pub trait NewDataFn<S = i16> = FnMut(&[S], &mut TfmxPlayer) -> NewDataCtlFlow;
 /// Whether to stop playing, or continue playing, with an optional [`PlayerCmd`]
 pub type NewDataCtlFlow = ControlFlow<(), Option<PlayerCmd>>;
 
 /// A command telling the player to do something
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum PlayerCmd {
     /// Switch to previous subsong
     Prev,
@@ -360,22 +662,464 @@ pub enum PlayerCmd {
     Next,
     /// Restart current song
     RestartSong,
-    /// Toggle stereo blending
+    /// Toggle between [`PlayerBuilder::BLEND_SEPARATION`] and full stereo separation
     ToggleBlend,
+    /// Set the stereo separation, in percent
+    SetSeparation(u8),
+    /// Set the pan position (-100 to 100) of the voice marked by the index
+    SetPan(u8, i8),
+    /// Toggle the emulated "LED" low-pass filter
+    ToggleLedFilter,
+    /// Set the master gain, see [`PlayerBuilder::master_gain`]
+    SetMasterGain(f32),
     /// Mute/unmute audio channel marked by the index
     ToggleCh(u8),
     /// Toggle whether to loop the current song
     ToggleLoopCurrentSong,
+    /// Seek to a position within the current song
+    Seek(Duration),
 }
 
 impl TfmxPlayer {
     /// Begin playback, using the specified callback to handle sample data produced by the player.
+    ///
+    /// This drives the player until the last subsong ends, or the callback requests to stop.
+    /// For driving the player yourself, see [`Self::render`].
+    ///
+    /// The samples are 16 bit, in the layout set by [`PlayerBuilder::channel_layout`].
     pub fn play(&mut self, handler: impl NewDataFn) {
+        self.play_as(handler);
+    }
+    /// Like [`Self::play`], but the callback gets samples of type `S`, such as `f32`
+    pub fn play_as<S: Sample>(&mut self, handler: impl NewDataFn<S>) {
         for row in self.header.text_rows() {
             log::info!("{row}");
         }
         play_loop(self, handler);
     }
+    /// Render samples of the current subsong into `out`, in the layout set by
+    /// [`PlayerBuilder::channel_layout`].
+    ///
+    /// Returns the number of samples written. If this is less than `out.len()`,
+    /// the current subsong has ended, and further calls will return 0 until another song
+    /// is started with [`Self::select_song`], [`Self::restart_song`] or [`Self::handle_cmd`].
+    ///
+    /// Only whole frames are written, so `out.len()` should be a multiple of the number
+    /// of channels. For [`ChannelLayout::Planar`], the written samples are split in half
+    /// between the channels.
+    pub fn render<S: Sample>(&mut self, out: &mut [S]) -> usize {
+        rendering::render(
+            &self.header,
+            &mut self.audio,
+            &mut self.tfmx,
+            &self.sample_buf,
+            self.ch_on,
+            self.layout,
+            out,
+        )
+    }
+    /// Start playing the subsong with the specified index from the beginning
+    pub fn select_song(&mut self, idx: SongIdx) {
+        self.song_idx = idx;
+        self.restart_song();
+    }
+    /// Restart the current subsong from the beginning
+    pub fn restart_song(&mut self) {
+        self.audio.reset();
+        self.tfmx = self.clean_tfmx.clone();
+        if self.song_idx >= MAX_SONGS {
+            return;
+        }
+        self.tfmx.init();
+        song::start_song(self.song_idx, 0, &self.header, &mut self.tfmx);
+        log::info!("Playing song {}", self.song_idx);
+    }
+    /// Continue playing the current subsong from `pos`.
+    ///
+    /// The song is restarted and the sequencer is run up to `pos` without mixing audio,
+    /// which is much faster than rendering. If the song ends before `pos`,
+    /// it is left finished.
+    pub fn seek(&mut self, pos: Duration) {
+        self.restart_song();
+        let target = duration::duration_to_samples(pos, self.tfmx.out_rate);
+        rendering::fast_forward(
+            &self.header,
+            &mut self.audio,
+            &mut self.tfmx,
+            &self.sample_buf,
+            self.ch_on,
+            target,
+        );
+        self.tfmx.events.discard_before(target);
+    }
+    /// How far into the current subsong the samples handed out so far go
+    #[must_use]
+    pub fn position(&self) -> Duration {
+        duration::samples_to_duration(self.audio.position(), self.tfmx.out_rate)
+    }
+    /// How many frames of the current subsong have been handed out so far.
+    ///
+    /// This is on the same scale as [`Event::frame`].
+    #[must_use]
+    pub const fn position_frames(&self) -> u64 {
+        self.audio.position()
+    }
+    /// The cue values, which the song sets to let games sync to the music.
+    ///
+    /// The sequencer runs ahead of the samples handed out, so these can be newer than
+    /// what is heard. Use [`EventKind::CueChanged`] events for exact timing.
+    #[must_use]
+    pub const fn cues(&self) -> [u16; 4] {
+        self.tfmx.idb.cues()
+    }
+    /// Levels and state of each hardware voice during the last block that was mixed,
+    /// if [`PlayerBuilder::voice_meters`] is on.
+    ///
+    /// A block is 2048 frames long, except at the end of a song, and mixing runs ahead of
+    /// the samples handed out by up to a few blocks.
+    /// Voices that are muted or not in use are silent.
+    #[must_use]
+    pub fn voice_meters(&self) -> Option<&[VoiceMeter; MAX_CHANNELS as usize]> {
+        self.audio.meters()
+    }
+    /// Take the events that happened since the last call, such as notes, pattern changes
+    /// and cue changes.
+    ///
+    /// Call this after [`Self::render`], or from the [`Self::play`] callback.
+    /// Events are made as the song gets rendered, which runs ahead of the samples handed
+    /// out, so an event is heard once [`Self::position_frames`] reaches its frame.
+    /// Only the latest few thousand events are kept.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = Event> {
+        self.tfmx.events.drain()
+    }
+    /// Where the looping part of the current subsong starts, once it has looped
+    #[must_use]
+    pub fn loop_point(&self) -> Option<Duration> {
+        let rate = self.tfmx.out_rate;
+        (self.tfmx.loop_tracker.loop_start).map(|start| duration::samples_to_duration(start, rate))
+    }
+    /// How many times the current subsong has been played through
+    #[must_use]
+    pub const fn completed_loops(&self) -> u32 {
+        self.tfmx.loop_tracker.passes
+    }
+    /// How many samples of the current subsong went beyond 16 bit full scale so far.
+    ///
+    /// These are clipped in `i16` and `i32` output, and exceed 1.0 in `f32` output.
+    /// If this isn't 0, lower the [`PlayerBuilder::master_gain`] or turn on the
+    /// [`PlayerBuilder::limiter`] to avoid distortion.
+    #[must_use]
+    pub const fn clipped_samples(&self) -> u64 {
+        self.audio.clipped()
+    }
+    /// Play note `note` (0 to 63) with macro `macro_num` at volume `volume` (0 to 15) on
+    /// `channel` as a sound effect, over the music.
+    ///
+    /// The effect starts on the next tick. Like in the original player, the music can
+    /// take the channel back with its next note unless the channel is locked, see
+    /// [`Self::lock_channel`], and a locked channel refuses effects with a lower `priority`
+    /// than the one it was locked with. Until the effect starts, effects with a lower
+    /// `priority` are refused too.
+    ///
+    /// # Errors
+    ///
+    /// Errors if no song is playing, an argument is out of range, or the channel is locked
+    /// or has an effect waiting with a higher priority
+    pub fn trigger_sfx(
+        &mut self,
+        channel: u8,
+        note: u8,
+        macro_num: u8,
+        volume: u8,
+        priority: u8,
+    ) -> Result<(), SfxError> {
+        let ch = self.sfx_channel(channel)?;
+        if note >= 0x40 {
+            return Err(SfxError::InvalidNote(note));
+        }
+        if usize::from(macro_num) >= self.header.macro_count {
+            return Err(SfxError::NoSuchMacro(macro_num));
+        }
+        if volume > 0xF {
+            return Err(SfxError::InvalidVolume(volume));
+        }
+        let code = u32::from_be_bytes([note, macro_num, volume << 4 | channel, 0]);
+        if !song::queue_sfx(&mut self.tfmx, ch, code, priority) {
+            return Err(SfxError::Locked);
+        }
+        Ok(())
+    }
+    /// Keep the music off `channel` for `ticks` ticks, so sound effects played on it
+    /// aren't cut off. Effects with a lower `priority` are refused until the lock runs out.
+    ///
+    /// # Errors
+    ///
+    /// Errors if no song is playing, the channel doesn't exist, or the channel is already
+    /// locked with a higher priority
+    pub fn lock_channel(&mut self, channel: u8, priority: u8, ticks: u8) -> Result<(), SfxError> {
+        let ch = self.sfx_channel(channel)?;
+        if !song::lock_channel(&mut self.tfmx, ch, priority, ticks) {
+            return Err(SfxError::Locked);
+        }
+        Ok(())
+    }
+    /// Index of `channel`, if sound effects can be played on it
+    fn sfx_channel(&self, channel: u8) -> Result<usize, SfxError> {
+        if !self.tfmx.mdb.player_enable {
+            return Err(SfxError::NotPlaying);
+        }
+        let channels = if self.tfmx.multimode { MAX_CHANNELS } else { 4 };
+        if channel >= channels {
+            return Err(SfxError::NoSuchChannel(channel));
+        }
+        Ok(usize::from(channel))
+    }
+    /// Whether the current subsong has finished playing
+    #[must_use]
+    pub const fn song_finished(&self) -> bool {
+        !self.tfmx.mdb.player_enable && self.audio.is_drained()
+    }
+    /// Execute a [`PlayerCmd`]
+    pub fn handle_cmd(&mut self, cmd: PlayerCmd) {
+        self.exec_cmd(cmd);
+    }
+    /// Execute a [`PlayerCmd`], returning whether a song was (re)started
+    fn exec_cmd(&mut self, cmd: PlayerCmd) -> bool {
+        match cmd {
+            PlayerCmd::Prev => {
+                self.select_song(self.prev_valid_song(self.song_idx));
+                return true;
+            }
+            PlayerCmd::Next => {
+                self.select_song(self.next_valid_song(self.song_idx));
+                return true;
+            }
+            PlayerCmd::RestartSong => {
+                self.restart_song();
+                return true;
+            }
+            PlayerCmd::Seek(pos) => {
+                self.seek(pos);
+                return true;
+            }
+            PlayerCmd::ToggleBlend => {
+                let blend = self.audio.separation() != PlayerBuilder::BLEND_SEPARATION;
+                self.audio.set_separation(if blend {
+                    PlayerBuilder::BLEND_SEPARATION
+                } else {
+                    100
+                });
+                log::info!("Stereo blend {}", blend.on_off());
+            }
+            PlayerCmd::SetSeparation(percent) => {
+                self.audio.set_separation(percent);
+                log::info!("Stereo separation {percent}%");
+            }
+            PlayerCmd::SetPan(ch_idx, pan) => {
+                if usize::from(ch_idx) < self.ch_on.len() {
+                    self.audio.set_pan(usize::from(ch_idx), pan);
+                    log::info!("Pan positions: {:?}", self.audio.pans());
+                } else {
+                    log::warn!("No such channel: {ch_idx}");
+                }
+            }
+            PlayerCmd::ToggleLedFilter => {
+                let filter = self.audio.filter_mut();
+                filter.set_led(!filter.led());
+                log::info!("LED filter {}", filter.led().on_off());
+            }
+            PlayerCmd::SetMasterGain(gain) => {
+                self.audio.set_master_gain(gain);
+                log::info!("Master gain {gain}");
+            }
+            PlayerCmd::ToggleCh(ch_idx) => match self.ch_on.get_mut(ch_idx as usize) {
+                Some(ch) => {
+                    *ch ^= true;
+                    let on_off = |b| if b { "X" } else { "_" };
+                    log::info!("Channel status: {:?}", self.ch_on.map(on_off));
+                }
+                None => {
+                    log::warn!("No such channel: {ch_idx}");
+                }
+            },
+            PlayerCmd::ToggleLoopCurrentSong => {
+                self.loop_current_song ^= true;
+                log::info!("Loop current song {}", self.loop_current_song.on_off());
+            }
+        }
+        false
+    }
+    /// Index of the first valid subsong after `idx`, or [`MAX_SONGS`] if there is none
+    fn next_valid_song(&self, idx: SongIdx) -> SongIdx {
+        (idx.saturating_add(1)..MAX_SONGS)
+            .find(|&i| self.subsongs[usize::from(i)] == SubsongKind::Valid)
+            .unwrap_or(MAX_SONGS)
+    }
+    /// Index of the last valid subsong before `idx`, or `idx` itself if there is none
+    fn prev_valid_song(&self, idx: SongIdx) -> SongIdx {
+        (0..idx.min(MAX_SONGS))
+            .rev()
+            .find(|&i| self.subsongs[usize::from(i)] == SubsongKind::Valid)
+            .unwrap_or(idx)
+    }
+    /// Returns what each slot of the song table holds
+    #[must_use]
+    pub const fn subsongs(&self) -> &[SubsongKind] {
+        &self.subsongs
+    }
+    /// Estimate how long the subsong with the specified index lasts.
+    ///
+    /// This runs the sequencer without mixing any audio, so it's a lot faster than rendering.
+    /// Songs that neither end nor loop within an hour are reported as lasting an hour.
+    #[must_use]
+    pub fn estimate_duration(&self, idx: SongIdx) -> SongDuration {
+        duration::estimate(&self.header, &self.clean_tfmx, idx)
+    }
+    /// Render the subsong with the specified index to `out` as a 16 bit stereo WAV file.
+    ///
+    /// This doesn't affect playback. The channel mutes and stereo settings of the
+    /// player apply, and the header text is stored as a comment in a LIST/INFO chunk.
+    ///
+    /// Returns the number of samples that had to be clipped, see [`Self::clipped_samples`].
+    ///
+    /// # Errors
+    ///
+    /// Errors on I/O error, or if the song is too long to fit in a WAV file
+    pub fn write_wav(
+        &self,
+        idx: SongIdx,
+        options: &WavOptions,
+        out: impl Write + Seek,
+    ) -> std::io::Result<u64> {
+        wav::write_song(self, idx, options, out)
+    }
+    /// Render every valid subsong to a WAV file, at the path `path_for` returns for its index.
+    ///
+    /// See [`Self::write_wav`].
+    ///
+    /// # Errors
+    ///
+    /// Stops at the first subsong that fails to be written, and returns the error
+    pub fn export_wavs(
+        &self,
+        options: &WavOptions,
+        mut path_for: impl FnMut(SongIdx) -> PathBuf,
+    ) -> std::io::Result<()> {
+        for idx in 0..MAX_SONGS {
+            if self.subsongs[usize::from(idx)] != SubsongKind::Valid {
+                continue;
+            }
+            let path = path_for(idx);
+            log::info!("Writing song {idx} to {}", path.display());
+            let mut file = BufWriter::new(File::create(path)?);
+            self.write_wav(idx, options, &mut file)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+    /// Disassemble the macro with the specified number, or return `None` if there's no such
+    /// macro
+    #[must_use]
+    pub fn disassemble_macro(&self, num: u8) -> Option<Vec<MacroStep>> {
+        disasm::disassemble_macro(&self.header, &self.clean_tfmx.editbuf, num)
+    }
+    /// Disassemble the pattern with the specified number, or return `None` if there's no
+    /// such pattern
+    #[must_use]
+    pub fn disassemble_pattern(&self, num: u8) -> Option<Vec<PatternStep>> {
+        disasm::disassemble_pattern(&self.header, &self.clean_tfmx.editbuf, num)
+    }
+    /// Disassemble the track steps of the subsong with the specified index, or return
+    /// `None` if its song table slot doesn't point into the track table
+    #[must_use]
+    pub fn disassemble_track(&self, idx: SongIdx) -> Option<Vec<TrackStep>> {
+        disasm::disassemble_track(&self.header, &self.clean_tfmx.editbuf, idx)
+    }
+    /// Find the regions of the sample data that the macros of the module play, with their
+    /// loops
+    #[must_use]
+    pub fn instruments(&self) -> Vec<Instrument> {
+        instruments::analyze(
+            &self.header,
+            &self.clean_tfmx.editbuf,
+            self.sample_buf.len(),
+        )
+    }
+    /// Write the samples of `instrument` to `out`, at [`INSTRUMENT_RATE`]
+    ///
+    /// # Errors
+    ///
+    /// Errors on I/O error, or if the instrument is outside of the sample data
+    pub fn write_instrument(
+        &self,
+        instrument: &Instrument,
+        format: InstrumentFormat,
+        out: impl Write,
+    ) -> std::io::Result<()> {
+        instruments::write(instrument, &self.sample_buf, format, out)
+    }
+    /// Write every instrument of the module to the file `path_for` returns for its index
+    /// in [`Self::instruments`]
+    ///
+    /// # Errors
+    ///
+    /// Errors on I/O error
+    pub fn export_instruments(
+        &self,
+        format: InstrumentFormat,
+        mut path_for: impl FnMut(usize, &Instrument) -> PathBuf,
+    ) -> std::io::Result<()> {
+        for (idx, instrument) in self.instruments().iter().enumerate() {
+            let path = path_for(idx, instrument);
+            log::info!("Writing instrument {idx} to {}", path.display());
+            let mut file = BufWriter::new(File::create(path)?);
+            self.write_instrument(instrument, format, &mut file)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+    /// Run the subsong with the specified index through the sequencer once, and write its
+    /// notes to `out` as a type 1 Standard MIDI File, with a track for each channel.
+    ///
+    /// Macros become program changes, and portamentos and vibratos become pitch bends,
+    /// with a range of 12 semitones. Each MIDI tick is a tick of the player.
+    ///
+    /// # Errors
+    ///
+    /// Errors on I/O error
+    pub fn write_midi(&self, idx: SongIdx, out: impl Write) -> std::io::Result<()> {
+        midi::write_song(self, idx, out)
+    }
+    /// Render each hardware voice of the subsong with the specified index into its own
+    /// mono buffer.
+    ///
+    /// The voices are mixed like for normal playback, but channel mutes, output filters
+    /// and stereo settings don't apply. Voices that the song doesn't use are silent.
+    #[must_use]
+    pub fn render_stems(&self, idx: SongIdx, options: &WavOptions) -> Stems {
+        wav::stem_buffers(self, idx, options)
+    }
+    /// Render each hardware voice of the subsong with the specified index to its own
+    /// mono WAV file, at the path `path_for` returns for the voice index.
+    ///
+    /// See [`Self::render_stems`].
+    ///
+    /// # Errors
+    ///
+    /// Errors on I/O error, or if the song is too long to fit in a WAV file
+    pub fn export_stems(
+        &self,
+        idx: SongIdx,
+        options: &WavOptions,
+        path_for: impl FnMut(u8) -> PathBuf,
+    ) -> std::io::Result<()> {
+        wav::write_stems(self, idx, options, path_for)
+    }
+    /// Returns information about the loaded module
+    #[must_use]
+    pub fn module_info(&self) -> ModuleInfo {
+        ModuleInfo::new(&self.header, &self.clean_tfmx, &self.subsongs)
+    }
     /// Returns the index of the currently active song
     #[must_use]
     pub const fn current_song_index(&self) -> SongIdx {
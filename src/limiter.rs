@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+/// Peaks are brought down to this level
+const THRESHOLD: f64 = 32_000.0;
+/// How far ahead the limiter looks for peaks, in milliseconds
+const LOOKAHEAD_MS: u32 = 5;
+/// How long the gain takes to recover from full reduction, in milliseconds
+const RELEASE_MS: u32 = 200;
+
+/// Look-ahead peak limiter for stereo frames.
+///
+/// The gain needed by each frame is the minimum over the look-ahead window, smoothed by
+/// averaging over the same window, so it is already reduced when a peak arrives.
+/// This delays the output by the look-ahead time.
+#[derive(Debug, Clone)]
+pub(crate) struct Limiter {
+    /// Length of the look-ahead window in frames, including the current one
+    window: u32,
+    release_step: f64,
+    /// Frames waiting to be output
+    delay: VecDeque<[i32; 2]>,
+    /// Candidates for the minimum needed gain in the window, as (frame number, gain)
+    mins: VecDeque<(u64, f64)>,
+    /// Minimum needed gains of the last `window` frames, and their sum
+    history: VecDeque<f64>,
+    history_sum: f64,
+    frame: u64,
+    gain: f64,
+}
+
+impl Limiter {
+    pub(crate) fn new(rate: u32) -> Self {
+        let window = rate * LOOKAHEAD_MS / 1000 + 1;
+        Self {
+            window,
+            release_step: 1000.0 / f64::from(rate * RELEASE_MS),
+            delay: VecDeque::with_capacity(window as usize),
+            mins: VecDeque::with_capacity(window as usize),
+            history: VecDeque::with_capacity(window as usize),
+            history_sum: 0.0,
+            frame: 0,
+            gain: 1.0,
+        }
+    }
+    /// Forget the past frames
+    pub(crate) fn reset(&mut self) {
+        self.delay.clear();
+        self.mins.clear();
+        self.history.clear();
+        self.history_sum = 0.0;
+        self.frame = 0;
+        self.gain = 1.0;
+    }
+    /// Feed in a frame, getting back the frame from the look-ahead time ago, once there is one
+    pub(crate) fn process(&mut self, frame: [i32; 2]) -> Option<[f64; 2]> {
+        let peak = frame.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+        let needed = (THRESHOLD / f64::from(peak)).min(1.0);
+        self.frame += 1;
+        while self.mins.back().is_some_and(|&(_, gain)| gain >= needed) {
+            self.mins.pop_back();
+        }
+        self.mins.push_back((self.frame, needed));
+        while self
+            .mins
+            .front()
+            .is_some_and(|&(n, _)| n + u64::from(self.window) <= self.frame)
+        {
+            self.mins.pop_front();
+        }
+        let min = self.mins.front().map_or(1.0, |&(_, gain)| gain);
+        self.history.push_back(min);
+        self.history_sum += min;
+        if self.history.len() > self.window as usize {
+            self.history_sum -= self.history.pop_front().unwrap_or(0.0);
+        }
+        self.delay.push_back(frame);
+        if self.delay.len() < self.window as usize {
+            return None;
+        }
+        let average = self.history_sum / f64::from(self.window);
+        self.gain = average.min(self.gain + self.release_step);
+        self.delay
+            .pop_front()
+            .map(|frame| frame.map(|s| f64::from(s) * self.gain))
+    }
+    /// Get the frames that are still waiting to be output
+    pub(crate) fn flush(&mut self) -> Vec<[f64; 2]> {
+        let mut out = Vec::with_capacity(self.delay.len());
+        let mut pending = self.delay.len();
+        while pending > 0 {
+            if let Some(frame) = self.process([0; 2]) {
+                out.push(frame);
+                pending -= 1;
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,50 @@
+use {crate::song::Pdblk, std::collections::HashMap};
+
+/// The state that decides where the sequencer goes after a track step:
+/// the track position, and the counter of the track loop command.
+type StepKey = (u16, i16);
+
+/// Tells when a song has looped, by watching for track steps it has already been at
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoopTracker {
+    /// Time of the current tick, in (per channel) samples since the song started
+    pub(crate) clock: u64,
+    /// How many passes to play before ending the song, 0 for no limit
+    pub(crate) max_passes: u32,
+    /// How many passes have been completed
+    pub(crate) passes: u32,
+    /// Where the looping part of the song starts, once it has looped
+    pub(crate) loop_start: Option<u64>,
+    /// Whether the last requested pass is complete, which ends the song at the start of
+    /// the current tick
+    pub(crate) ended: bool,
+    /// Track steps reached during the current pass, and when
+    visited: HashMap<StepKey, u64>,
+}
+
+impl LoopTracker {
+    pub(crate) fn new(max_passes: u32) -> Self {
+        Self {
+            max_passes,
+            ..Self::default()
+        }
+    }
+    /// Record that the sequencer fetched a new track step.
+    ///
+    /// Returns whether the song should end, because the last requested pass is complete.
+    pub(crate) fn step_fetched(&mut self, pdblk: &Pdblk, track_loop: i16) -> bool {
+        let key = (pdblk.curr_pos, track_loop);
+        let Some(&start) = self.visited.get(&key) else {
+            self.visited.insert(key, self.clock);
+            return false;
+        };
+        self.passes += 1;
+        self.loop_start.get_or_insert(start);
+        log::debug!("Pass {} complete, looping to step {}", self.passes, key.0);
+        // The next pass goes through the same steps again
+        self.visited.clear();
+        self.visited.insert(key, self.clock);
+        self.ended = self.max_passes != 0 && self.passes >= self.max_passes;
+        self.ended
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{CdbArr, HdbArr, MAX_CHANNELS};
+
+/// Level of a voice playing the loudest sample value at full volume
+const FULL_LEVEL: f32 = 128.0 * 64.0;
+
+/// What a hardware voice did during the last mixed block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VoiceMeter {
+    /// Highest absolute sample value, where 1.0 is the loudest a voice can play
+    pub peak: f32,
+    /// Root mean square of the samples, on the same scale as [`Self::peak`]
+    pub rms: f32,
+    /// Amiga period the voice plays at, at the end of the block
+    pub period: u16,
+    /// Volume of the voice (0 to 64), at the end of the block
+    pub volume: u8,
+    /// Every nth sample of the voice, see [`crate::PlayerBuilder::scope_decimation`]
+    pub scope: Vec<f32>,
+}
+
+/// Measures the voices as they get mixed
+#[derive(Debug, Clone)]
+pub(crate) struct MeterTap {
+    decimation: usize,
+    peaks: [u32; MAX_CHANNELS as usize],
+    squares: [u64; MAX_CHANNELS as usize],
+    scopes: [Vec<f32>; MAX_CHANNELS as usize],
+    meters: [VoiceMeter; MAX_CHANNELS as usize],
+}
+
+impl MeterTap {
+    pub(crate) fn new(decimation: u16) -> Self {
+        Self {
+            decimation: decimation.into(),
+            peaks: [0; MAX_CHANNELS as usize],
+            squares: [0; MAX_CHANNELS as usize],
+            scopes: Default::default(),
+            meters: Default::default(),
+        }
+    }
+    pub(crate) fn reset(&mut self) {
+        self.peaks.fill(0);
+        self.squares.fill(0);
+        self.scopes.iter_mut().for_each(Vec::clear);
+        self.meters = Default::default();
+    }
+    /// Take in the mixed `samples` of `voice`, which start `offset` samples into the block
+    pub(crate) fn feed(&mut self, voice: usize, offset: usize, samples: &[i32]) {
+        for (i, &sample) in samples.iter().enumerate() {
+            self.peaks[voice] = self.peaks[voice].max(sample.unsigned_abs());
+            self.squares[voice] += u64::from(sample.unsigned_abs()).pow(2);
+            if self.decimation != 0 && (offset + i).is_multiple_of(self.decimation) {
+                self.scopes[voice].push(f64::from(sample) as f32 / FULL_LEVEL);
+            }
+        }
+    }
+    /// Finish a block of `len` samples, where the voices are left as in `hdb`
+    pub(crate) fn finish_block(&mut self, len: usize, hdb: &HdbArr, cdb: &CdbArr) {
+        for (voice, meter) in self.meters.iter_mut().enumerate() {
+            let hw = &hdb[voice];
+            let mean = (self.squares[voice] / len.max(1) as u64) as u32;
+            meter.peak = f64::from(self.peaks[voice]) as f32 / FULL_LEVEL;
+            meter.rms = f64::from(mean).sqrt() as f32 / FULL_LEVEL;
+            meter.period = hw.cdb_idx.map_or(0, |idx| cdb[idx].period());
+            meter.volume = hw.vol;
+            std::mem::swap(&mut meter.scope, &mut self.scopes[voice]);
+            self.scopes[voice].clear();
+        }
+        self.peaks.fill(0);
+        self.squares.fill(0);
+    }
+    pub(crate) const fn meters(&self) -> &[VoiceMeter; MAX_CHANNELS as usize] {
+        &self.meters
+    }
+}
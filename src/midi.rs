@@ -0,0 +1,202 @@
+use {
+    crate::{
+        MAX_CHANNELS, SongIdx, TfmxPlayer, duration::MAX_ESTIMATE_SECS, events::EventKind,
+        rendering::run_tick, song,
+    },
+    std::io::{self, Write},
+};
+
+/// MIDI note of TFMX note 0
+const NOTE_OFFSET: u8 = 36;
+/// Pitch bend range set on every channel, in semitones
+const BEND_RANGE: u8 = 12;
+const BEND_CENTER: u16 = 0x2000;
+/// CIA timer clocks per second, as counted by `e_clocks`
+const CIA_CLOCKS_PER_SEC: u64 = 715_910;
+
+/// Events of a MIDI track, with delta times
+#[derive(Default)]
+struct Track {
+    data: Vec<u8>,
+    last_tick: u32,
+}
+
+impl Track {
+    fn event(&mut self, tick: u32, bytes: &[u8]) {
+        write_vlq(&mut self.data, tick - self.last_tick);
+        self.data.extend_from_slice(bytes);
+        self.last_tick = tick;
+    }
+    fn meta(&mut self, tick: u32, kind: u8, payload: &[u8]) {
+        self.event(tick, &[0xFF, kind]);
+        write_vlq(&mut self.data, payload.len() as u32);
+        self.data.extend_from_slice(payload);
+    }
+    fn write_chunk(mut self, tick: u32, out: &mut impl Write) -> io::Result<()> {
+        // End of track
+        self.meta(tick, 0x2F, &[]);
+        out.write_all(b"MTrk")?;
+        out.write_all(&(self.data.len() as u32).to_be_bytes())?;
+        out.write_all(&self.data)
+    }
+}
+
+/// Variable length quantity, 7 bits per byte with the most significant bits first
+fn write_vlq(out: &mut Vec<u8>, value: u32) {
+    let mut shift = 28;
+    while shift > 0 && value >> shift == 0 {
+        shift -= 7;
+    }
+    while shift > 0 {
+        out.push(0x80 | (value >> shift) as u8 & 0x7F);
+        shift -= 7;
+    }
+    out.push(value as u8 & 0x7F);
+}
+
+/// A TFMX channel and the MIDI track it is written to
+struct Channel {
+    midi_ch: u8,
+    track: Track,
+    used: bool,
+    note: Option<u8>,
+    program: Option<u8>,
+    /// Period of the playing note, which pitch bends are relative to
+    base_period: u16,
+    /// Whether a portamento or a vibrato has run since the note started
+    bending: bool,
+    bend: u16,
+}
+
+impl Channel {
+    fn new(midi_ch: u8) -> Self {
+        let mut track = Track::default();
+        track.meta(0, 0x03, format!("Channel {midi_ch}").as_bytes());
+        // Set the pitch bend range with RPN 0
+        let cc = 0xB0 | midi_ch;
+        for [controller, value] in [[101, 0], [100, 0], [6, BEND_RANGE], [38, 0]] {
+            track.event(0, &[cc, controller, value]);
+        }
+        Self {
+            midi_ch,
+            track,
+            used: false,
+            note: None,
+            program: None,
+            base_period: 0,
+            bending: false,
+            bend: BEND_CENTER,
+        }
+    }
+    fn note_on(&mut self, tick: u32, note: u8, macro_num: u8, velocity: u8) {
+        self.note_off(tick);
+        self.used = true;
+        let program = macro_num & 0x7F;
+        if self.program != Some(program) {
+            self.track.event(tick, &[0xC0 | self.midi_ch, program]);
+            self.program = Some(program);
+        }
+        if self.bend != BEND_CENTER {
+            self.set_bend(tick, BEND_CENTER);
+        }
+        self.bending = false;
+        self.base_period = song::note_period(note);
+        let key = (note + NOTE_OFFSET).min(0x7F);
+        let velocity = (u16::from(velocity) * 127 / 15).max(1) as u8;
+        self.track
+            .event(tick, &[0x90 | self.midi_ch, key, velocity]);
+        self.note = Some(key);
+    }
+    fn note_off(&mut self, tick: u32) {
+        if let Some(key) = self.note.take() {
+            self.track.event(tick, &[0x80 | self.midi_ch, key, 0x40]);
+        }
+    }
+    /// Follow the period of the channel with pitch bends
+    fn follow_period(&mut self, tick: u32, period: u16) {
+        if !self.bending || self.note.is_none() || period == 0 || self.base_period == 0 {
+            return;
+        }
+        let semitones = 12.0 * (f64::from(self.base_period) / f64::from(period)).log2();
+        let bend = semitones / f64::from(BEND_RANGE);
+        let bend = f64::from(BEND_CENTER)
+            .mul_add(bend, f64::from(BEND_CENTER))
+            .round()
+            .clamp(0.0, 16_383.0) as u16;
+        if bend != self.bend {
+            self.set_bend(tick, bend);
+        }
+    }
+    fn set_bend(&mut self, tick: u32, bend: u16) {
+        let [lsb, msb] = [bend as u8 & 0x7F, (bend >> 7) as u8];
+        self.track.event(tick, &[0xE0 | self.midi_ch, lsb, msb]);
+        self.bend = bend;
+    }
+}
+
+/// Run subsong `idx` of `player` through the sequencer once, writing its notes to `out`
+/// as a type 1 Standard MIDI File
+pub(crate) fn write_song(player: &TfmxPlayer, idx: SongIdx, mut out: impl Write) -> io::Result<()> {
+    let header = &player.header;
+    let mut tfmx = player.clean_tfmx.start_copy(header, idx, 1);
+    let max_samples = MAX_ESTIMATE_SECS * u64::from(tfmx.out_rate);
+    let mut conductor = Track::default();
+    let title = header
+        .text_rows()
+        .map(|row| row.trim_matches('\0').trim())
+        .find(|row| !row.is_empty());
+    if let Some(title) = title {
+        conductor.meta(0, 0x03, title.as_bytes());
+    }
+    let mut channels: Vec<Channel> = (0..MAX_CHANNELS).map(Channel::new).collect();
+    // One MIDI tick per player tick, with 4 pattern steps per quarter note at the start
+    let mut division = None;
+    let mut e_rem = 0;
+    let mut tick = 0;
+    while tfmx.mdb.player_enable && tfmx.loop_tracker.clock < max_samples {
+        run_tick(header, &mut tfmx, &mut e_rem);
+        for event in tfmx.events.drain() {
+            match event.kind {
+                EventKind::TempoChange { speed, e_clocks } => {
+                    let division =
+                        *division.get_or_insert_with(|| (4 * (u32::from(speed) + 1)).min(0x7FFF));
+                    let quarter_us =
+                        u64::from(e_clocks) * 1_000_000 * u64::from(division) / CIA_CLOCKS_PER_SEC;
+                    let quarter_us = quarter_us.min(0xFF_FFFF) as u32;
+                    conductor.meta(tick, 0x51, &quarter_us.to_be_bytes()[1..]);
+                }
+                EventKind::NoteOn {
+                    channel,
+                    note,
+                    macro_num,
+                    velocity,
+                    ..
+                } => channels[usize::from(channel)].note_on(tick, note, macro_num, velocity),
+                EventKind::KeyUp { channel } => channels[usize::from(channel)].note_off(tick),
+                EventKind::Portamento { channel, .. } | EventKind::Vibrato { channel, .. } => {
+                    channels[usize::from(channel)].bending = true;
+                }
+                _ => {}
+            }
+        }
+        for (ch, channel) in channels.iter_mut().enumerate() {
+            channel.follow_period(tick, tfmx.cdb[ch].period());
+        }
+        tick += 1;
+    }
+    for channel in &mut channels {
+        channel.note_off(tick);
+    }
+    channels.retain(|channel| channel.used);
+
+    out.write_all(b"MThd")?;
+    out.write_all(&6u32.to_be_bytes())?;
+    out.write_all(&1u16.to_be_bytes())?;
+    out.write_all(&(channels.len() as u16 + 1).to_be_bytes())?;
+    out.write_all(&(division.unwrap_or(24) as u16).to_be_bytes())?;
+    conductor.write_chunk(tick, &mut out)?;
+    for channel in channels {
+        channel.track.write_chunk(tick, &mut out)?;
+    }
+    Ok(())
+}
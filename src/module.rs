@@ -0,0 +1,456 @@
+use {
+    crate::{
+        EditBuf, MAX_CHANNELS, MAX_SONGS, MdatLoadError, TEXT_ROW_LEN, TEXT_ROWS,
+        disasm::{MacroCommand, PatternCommand, TrackCommand},
+        header::{HEADER_SIZE, Header},
+    },
+    std::io::Cursor,
+};
+
+/// Entries in the pattern and macro offset tables
+const TABLE_ENTRIES: usize = 128;
+const TABLE_SIZE: usize = TABLE_ENTRIES * 4;
+/// Where the tables are when the header leaves their offsets at 0: tracks, patterns, macros
+const DEFAULT_OFFSETS: [usize; 3] = [0x800, 0x400, 0x600];
+/// Byte offsets of the fields of the header that aren't modelled
+const PAD_RANGE: std::ops::Range<usize> = 10..16;
+const TEXT_OFFSET: usize = 16;
+const SONGS_OFFSET: usize = 0x100;
+const MUTE_OFFSET: usize = 0x1C0;
+const TABLE_OFFSETS_OFFSET: usize = 0x1D0;
+const TAIL_RANGE: std::ops::Range<usize> = 0x1DC..HEADER_SIZE;
+
+/// A song table slot of a [`Module`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SongEntry {
+    /// First step of the track table that belongs to the song
+    pub first_step: u16,
+    /// Last step of the track table that belongs to the song
+    pub last_step: u16,
+    /// Speed of the song, see [`crate::SubsongInfo::tempo`]
+    pub tempo: u16,
+}
+
+/// The steps of a pattern, as stored in the module
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pattern {
+    /// Four bytes per step
+    pub steps: Vec<[u8; 4]>,
+}
+
+impl Pattern {
+    /// The decoded steps
+    pub fn commands(&self) -> impl Iterator<Item = PatternCommand> {
+        self.steps
+            .iter()
+            .map(|&bytes| PatternCommand::decode(bytes))
+    }
+}
+
+/// The steps of a macro, as stored in the module
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Macro {
+    /// Four bytes per step
+    pub steps: Vec<[u8; 4]>,
+}
+
+impl Macro {
+    /// The decoded steps
+    pub fn commands(&self) -> impl Iterator<Item = MacroCommand> {
+        self.steps.iter().map(|&bytes| MacroCommand::decode(bytes))
+    }
+}
+
+/// What comes where in the .mdat data, so it can be written back the same way
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Tracks,
+    PatternTable,
+    MacroTable,
+    Pattern(usize),
+    Macro(usize),
+    /// Bytes that aren't part of anything else
+    Raw(Vec<u8>),
+}
+
+/// The parts of the .mdat data that aren't modelled
+#[derive(Debug, Clone)]
+struct Layout {
+    header_pad: [u8; 6],
+    header_tail: [u8; 36],
+    /// Which of the table offsets the header left at 0
+    default_offsets: [bool; 3],
+    /// The offset tables as stored, for their unused entries. The used ones are
+    /// rebuilt from where the data ends up, and left past the end of the data here,
+    /// which also ends the tables when patterns or macros are removed.
+    pattern_table: [u32; TABLE_ENTRIES],
+    macro_table: [u32; TABLE_ENTRIES],
+    /// Patterns that share their data with an earlier pattern
+    pattern_aliases: Vec<Option<usize>>,
+    /// Macros that share their data with an earlier macro
+    macro_aliases: Vec<Option<usize>>,
+    items: Vec<Item>,
+}
+
+/// A TFMX module, parsed from .mdat data.
+///
+/// Writing an unchanged module gives back the same .mdat data. Changed patterns, macros
+/// and track tables move whatever comes after them, and the offsets get updated.
+///
+/// Modules compare equal if they have the same contents, even if their data is laid out
+/// differently.
+#[derive(Debug, Clone)]
+pub struct Module {
+    /// Magic string at the start, like `TFMX-SONG `
+    pub magic: [u8; 10],
+    /// Text of the header
+    pub text: [[u8; TEXT_ROW_LEN as usize]; TEXT_ROWS as usize],
+    /// The song table
+    pub songs: [SongEntry; MAX_SONGS as usize],
+    /// Channel mute flags of the header, which the player doesn't use
+    pub mute: [i16; MAX_CHANNELS as usize],
+    /// The track table, with eight words per step
+    pub tracks: Vec<[u16; 8]>,
+    /// Patterns, by number
+    pub patterns: Vec<Pattern>,
+    /// Macros, by number
+    pub macros: Vec<Macro>,
+    layout: Layout,
+}
+
+impl PartialEq for Module {
+    fn eq(&self, other: &Self) -> bool {
+        self.magic == other.magic
+            && self.text == other.text
+            && self.songs == other.songs
+            && self.mute == other.mute
+            && self.tracks == other.tracks
+            && self.patterns == other.patterns
+            && self.macros == other.macros
+    }
+}
+
+impl Eq for Module {}
+
+impl Default for Module {
+    /// An empty module, laid out like the classic ones: the offset tables and the track
+    /// table where the header offsets of 0 put them, and the patterns and macros after the
+    /// track table, which the player expects to end where the first pattern starts
+    fn default() -> Self {
+        Self {
+            magic: *b"TFMX-SONG ",
+            text: [[b' '; TEXT_ROW_LEN as usize]; TEXT_ROWS as usize],
+            songs: [SongEntry::default(); MAX_SONGS as usize],
+            mute: [0; MAX_CHANNELS as usize],
+            tracks: Vec::new(),
+            patterns: Vec::new(),
+            macros: Vec::new(),
+            layout: Layout {
+                header_pad: [0; 6],
+                header_tail: [0; 36],
+                default_offsets: [true; 3],
+                pattern_table: [u32::MAX; TABLE_ENTRIES],
+                macro_table: [u32::MAX; TABLE_ENTRIES],
+                pattern_aliases: Vec::new(),
+                macro_aliases: Vec::new(),
+                items: vec![
+                    Item::Raw(vec![0; DEFAULT_OFFSETS[1] - HEADER_SIZE]),
+                    Item::PatternTable,
+                    Item::MacroTable,
+                    Item::Tracks,
+                ],
+            },
+        }
+    }
+}
+
+fn array<const N: usize>(data: &[u8], at: usize) -> [u8; N] {
+    std::array::from_fn(|i| data[at + i])
+}
+
+fn be_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([data[at], data[at + 1]])
+}
+
+fn be_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+/// Read an offset table, returning it with how many of its entries the player takes as
+/// valid, like `rebase_offsets`
+fn read_table(
+    data: &[u8],
+    at: usize,
+    words: usize,
+) -> Result<([u32; TABLE_ENTRIES], usize), MdatLoadError> {
+    let bytes = data
+        .get(at..at + TABLE_SIZE)
+        .ok_or(MdatLoadError::PreprocessError)?;
+    let table: [u32; TABLE_ENTRIES] = std::array::from_fn(|i| be_u32(bytes, i * 4));
+    for (i, &offset) in table.iter().enumerate() {
+        let y = offset
+            .checked_sub(HEADER_SIZE as u32)
+            .ok_or(MdatLoadError::PreprocessError)?;
+        if (y & 3) != 0 || (y >> 2) as usize > words {
+            return Ok((table, i));
+        }
+    }
+    Ok((table, TABLE_ENTRIES))
+}
+
+impl Module {
+    /// Parse .mdat data. Single-file (TFHD) modules aren't supported.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the data isn't a TFMX module, the player couldn't load it, or its
+    /// patterns, macros and tables overlap
+    pub fn from_mdat(data: &[u8]) -> Result<Self, MdatLoadError> {
+        let header = Header::from_reader(&mut Cursor::new(data))?;
+        // What the player loads into the edit buffer
+        let words = (data.len() - HEADER_SIZE).min(size_of::<EditBuf>() - 4) / 4;
+        let offsets = [header.track_start, header.patt_start, header.macro_start]
+            .map(|start| start * 4 + HEADER_SIZE);
+        let [track_pos, patt_pos, macro_pos] = offsets;
+        let (pattern_table, pattern_count) = read_table(data, patt_pos, words)?;
+        let (macro_table, macro_count) = read_table(data, macro_pos, words)?;
+
+        let mut starts = vec![
+            (track_pos, Item::Tracks),
+            (patt_pos, Item::PatternTable),
+            (macro_pos, Item::MacroTable),
+        ];
+        let mut aliases = |table: &[u32], count, item: fn(usize) -> Item| {
+            let mut aliases = vec![None; count];
+            for (i, &offset) in table[..count].iter().enumerate() {
+                let offset = offset as usize;
+                match table[..i].iter().position(|&prev| prev as usize == offset) {
+                    Some(prev) => aliases[i] = Some(prev),
+                    None => starts.push((offset, item(i))),
+                }
+            }
+            aliases
+        };
+        let pattern_aliases = aliases(&pattern_table, pattern_count, Item::Pattern);
+        let macro_aliases = aliases(&macro_table, macro_count, Item::Macro);
+        let mut pattern_table = pattern_table;
+        let mut macro_table = macro_table;
+        pattern_table[..pattern_count].fill(u32::MAX);
+        macro_table[..macro_count].fill(u32::MAX);
+        starts.sort_by_key(|&(start, _)| start);
+        if starts.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err(MdatLoadError::OverlappingData);
+        }
+
+        let mut module = Self {
+            magic: array(data, 0),
+            text: std::array::from_fn(|row| {
+                array(data, TEXT_OFFSET + row * usize::from(TEXT_ROW_LEN))
+            }),
+            songs: std::array::from_fn(|i| SongEntry {
+                first_step: header.song_starts[i],
+                last_step: header.song_ends[i],
+                tempo: header.song_tempos[i],
+            }),
+            mute: std::array::from_fn(|i| be_u16(data, MUTE_OFFSET + i * 2) as i16),
+            tracks: Vec::new(),
+            patterns: vec![Pattern::default(); pattern_count],
+            macros: vec![Macro::default(); macro_count],
+            layout: Layout {
+                header_pad: array(data, PAD_RANGE.start),
+                header_tail: array(data, TAIL_RANGE.start),
+                default_offsets: std::array::from_fn(|i| {
+                    be_u32(data, TABLE_OFFSETS_OFFSET + i * 4) == 0
+                }),
+                pattern_table,
+                macro_table,
+                pattern_aliases,
+                macro_aliases,
+                items: Vec::new(),
+            },
+        };
+        let mut pos = HEADER_SIZE;
+        for (i, (start, item)) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).map_or(data.len(), |&(next, _)| next);
+            if *start < pos || *start > data.len() {
+                return Err(MdatLoadError::OverlappingData);
+            }
+            if *start > pos {
+                module
+                    .layout
+                    .items
+                    .push(Item::Raw(data[pos..*start].to_vec()));
+            }
+            let region = &data[*start..end];
+            let used = match *item {
+                Item::PatternTable | Item::MacroTable => {
+                    if region.len() < TABLE_SIZE {
+                        return Err(MdatLoadError::OverlappingData);
+                    }
+                    TABLE_SIZE
+                }
+                Item::Tracks => {
+                    let (steps, _) = region.as_chunks::<16>();
+                    module.tracks = steps
+                        .iter()
+                        .map(|step| std::array::from_fn(|i| be_u16(step, i * 2)))
+                        .collect();
+                    steps.len() * 16
+                }
+                Item::Pattern(num) => {
+                    let (steps, _) = region.as_chunks::<4>();
+                    module.patterns[num].steps = steps.to_vec();
+                    steps.len() * 4
+                }
+                Item::Macro(num) => {
+                    let (steps, _) = region.as_chunks::<4>();
+                    module.macros[num].steps = steps.to_vec();
+                    steps.len() * 4
+                }
+                Item::Raw(_) => unreachable!(),
+            };
+            module.layout.items.push(item.clone());
+            pos = start + used;
+        }
+        if pos < data.len() {
+            module.layout.items.push(Item::Raw(data[pos..].to_vec()));
+        }
+        for (num, alias) in module.layout.pattern_aliases.iter().enumerate() {
+            if let Some(prev) = *alias {
+                module.patterns[num] = module.patterns[prev].clone();
+            }
+        }
+        for (num, alias) in module.layout.macro_aliases.iter().enumerate() {
+            if let Some(prev) = *alias {
+                module.macros[num] = module.macros[prev].clone();
+            }
+        }
+        Ok(module)
+    }
+    /// The decoded track steps
+    pub fn track_commands(&self) -> impl Iterator<Item = TrackCommand> {
+        self.tracks.iter().map(|&words| TrackCommand::decode(words))
+    }
+    /// Write the module as .mdat data.
+    ///
+    /// The offset tables have room for 128 patterns and 128 macros, so any more aren't
+    /// written.
+    #[must_use]
+    pub fn to_mdat(&self) -> Vec<u8> {
+        if self.patterns.len() > TABLE_ENTRIES || self.macros.len() > TABLE_ENTRIES {
+            log::warn!("Only the first {TABLE_ENTRIES} patterns and macros are written");
+        }
+        let mut out = vec![0; HEADER_SIZE];
+        let mut table_pos = [0; 3];
+        let mut pattern_pos = vec![None; self.patterns.len().min(TABLE_ENTRIES)];
+        let mut macro_pos = vec![None; self.macros.len().min(TABLE_ENTRIES)];
+        for item in &self.layout.items {
+            match *item {
+                Item::Tracks => {
+                    table_pos[0] = out.len();
+                    out.extend(
+                        self.tracks
+                            .iter()
+                            .flatten()
+                            .flat_map(|word| word.to_be_bytes()),
+                    );
+                }
+                Item::PatternTable | Item::MacroTable => {
+                    table_pos[if *item == Item::PatternTable { 1 } else { 2 }] = out.len();
+                    out.resize(out.len() + TABLE_SIZE, 0);
+                }
+                Item::Pattern(num) => {
+                    if let Some(pos) = pattern_pos.get_mut(num) {
+                        *pos = Some(out.len());
+                        out.extend(self.patterns[num].steps.concat());
+                    }
+                }
+                Item::Macro(num) => {
+                    if let Some(pos) = macro_pos.get_mut(num) {
+                        *pos = Some(out.len());
+                        out.extend(self.macros[num].steps.concat());
+                    }
+                }
+                Item::Raw(ref bytes) => out.extend_from_slice(bytes),
+            }
+        }
+        place_rest(
+            &mut out,
+            &self.patterns,
+            |pattern| &pattern.steps,
+            &self.layout.pattern_aliases,
+            &mut pattern_pos,
+        );
+        place_rest(
+            &mut out,
+            &self.macros,
+            |mac| &mac.steps,
+            &self.layout.macro_aliases,
+            &mut macro_pos,
+        );
+        let mut write_table =
+            |at: usize, raw: &[u32; TABLE_ENTRIES], positions: &[Option<usize>]| {
+                for (i, raw) in raw.iter().enumerate() {
+                    let offset = positions
+                        .get(i)
+                        .copied()
+                        .flatten()
+                        .map_or(*raw, |pos| pos as u32);
+                    out[at + i * 4..at + i * 4 + 4].copy_from_slice(&offset.to_be_bytes());
+                }
+            };
+        write_table(table_pos[1], &self.layout.pattern_table, &pattern_pos);
+        write_table(table_pos[2], &self.layout.macro_table, &macro_pos);
+        self.write_header(&mut out[..HEADER_SIZE], table_pos);
+        out
+    }
+    fn write_header(&self, header: &mut [u8], table_pos: [usize; 3]) {
+        header[..10].copy_from_slice(&self.magic);
+        header[PAD_RANGE].copy_from_slice(&self.layout.header_pad);
+        header[TEXT_OFFSET..SONGS_OFFSET].copy_from_slice(self.text.as_flattened());
+        let fields = (self.songs.iter().map(|song| song.first_step))
+            .chain(self.songs.iter().map(|song| song.last_step))
+            .chain(self.songs.iter().map(|song| song.tempo))
+            .chain(self.mute.iter().map(|&mute| mute as u16));
+        for (i, field) in fields.enumerate() {
+            header[SONGS_OFFSET + i * 2..SONGS_OFFSET + i * 2 + 2]
+                .copy_from_slice(&field.to_be_bytes());
+        }
+        for (i, pos) in table_pos.into_iter().enumerate() {
+            let offset = if self.layout.default_offsets[i] && pos == DEFAULT_OFFSETS[i] {
+                0
+            } else {
+                pos as u32
+            };
+            let at = TABLE_OFFSETS_OFFSET + i * 4;
+            header[at..at + 4].copy_from_slice(&offset.to_be_bytes());
+        }
+        header[TAIL_RANGE].copy_from_slice(&self.layout.header_tail);
+    }
+}
+
+/// Write the patterns or macros that the layout didn't place at the end of `out`.
+///
+/// The ones that shared their data with an earlier one share it again, if they're
+/// still the same.
+fn place_rest<T: PartialEq>(
+    out: &mut Vec<u8>,
+    items: &[T],
+    steps: impl Fn(&T) -> &[[u8; 4]],
+    aliases: &[Option<usize>],
+    positions: &mut [Option<usize>],
+) {
+    for num in 0..positions.len() {
+        if positions[num].is_some() {
+            continue;
+        }
+        positions[num] = match aliases.get(num).copied().flatten() {
+            Some(prev) if items[prev] == items[num] && positions[prev].is_some() => positions[prev],
+            _ => {
+                let pos = out.len();
+                out.extend(steps(&items[num]).concat());
+                Some(pos)
+            }
+        };
+    }
+}
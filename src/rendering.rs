@@ -1,51 +1,200 @@
-use {
-    crate::{
-        CdbArr, Hdb, MAX_CHANNELS, NewDataCtlFlow, TfmxCtx, TfmxPlayer, header::Header,
-        song::tfmx_irq_in,
-    },
-    std::ops::ControlFlow,
+use crate::{
+    CdbArr, Hdb, MAX_CHANNELS, TfmxCtx,
+    filter::OutputFilter,
+    header::Header,
+    interpolation::Interpolation,
+    limiter::Limiter,
+    meters::{MeterTap, VoiceMeter},
+    sample::{ChannelLayout, Sample},
+    song::tfmx_irq_in,
 };
 
 const BUFSIZE: usize = 16_384;
 const HALFBUFSIZE: usize = BUFSIZE / 2;
+/// How many samples [`crate::TfmxPlayer::play`] hands to the callback at once
+pub(crate) const CHUNK_LEN: usize = HALFBUFSIZE;
+/// Stereo separation that sounds like the stereo blend of the original player
+pub(crate) const BLEND_SEPARATION: u8 = 38;
+/// Voices 1 and 2 on the right, and the others on the left
+pub(crate) const DEFAULT_PANS: [i8; MAX_CHANNELS as usize] =
+    [-100, 100, 100, -100, -100, -100, -100, -100];
+/// Master gain that leaves the output as it is, in 16.16 fixed point
+const UNITY_GAIN: i64 = 1 << 16;
 
 pub(crate) struct AudioCtx {
-    buf: Box<[i16; BUFSIZE]>,
+    /// Mixed stereo frames, not yet converted to the output format
+    buf: Box<[i32; BUFSIZE]>,
     bhead: usize,
     btail: usize,
     blocksize: usize,
     multiplier: usize,
     e_rem: usize,
-    blend: bool,
+    /// Pan position of each voice, from -100 (left) to 100 (right)
+    pans: [i8; MAX_CHANNELS as usize],
+    /// Stereo separation in percent
+    separation: u8,
+    interpolation: Interpolation,
+    filter: OutputFilter,
+    /// Gain applied to the mix before limiting, in 16.16 fixed point
+    master_gain: i64,
+    limiter: Option<Limiter>,
+    /// How many output samples had to be clipped since the song started
+    clipped: u64,
+    meters: Option<MeterTap>,
     tbuf: Box<TBuf>,
+    /// Output of a single voice, before panning
+    vbuf: Vec<i32>,
     samples_done: usize,
+    /// How many frames have been handed out since the song started
+    frames_out: u64,
 }
 
 type TBuf = [i32; BUFSIZE];
 
 impl AudioCtx {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(interpolation: Interpolation, filter: OutputFilter) -> Self {
         let multiplier = 2;
+        let blocksize = HALFBUFSIZE / multiplier / 2;
         Self {
             buf: bytemuck::allocation::zeroed_box(),
             bhead: 0,
             btail: 0,
-            blocksize: HALFBUFSIZE / multiplier / 2,
+            blocksize,
             multiplier,
             e_rem: 0,
-            blend: true,
+            pans: DEFAULT_PANS,
+            separation: BLEND_SEPARATION,
+            interpolation,
+            filter,
+            master_gain: UNITY_GAIN,
+            limiter: None,
+            clipped: 0,
+            meters: None,
             tbuf: bytemuck::allocation::zeroed_box(),
+            vbuf: vec![0; blocksize],
             samples_done: 0,
+            frames_out: 0,
         }
     }
 
-    pub(crate) const fn toggle_blend(&mut self) {
-        self.blend ^= true;
+    /// A new context with the same settings as this one
+    pub(crate) fn new_like(&self) -> Self {
+        let mut filter = self.filter.clone();
+        filter.reset();
+        let mut limiter = self.limiter.clone();
+        if let Some(limiter) = &mut limiter {
+            limiter.reset();
+        }
+        Self {
+            pans: self.pans,
+            separation: self.separation,
+            master_gain: self.master_gain,
+            limiter,
+            ..Self::new(self.interpolation, filter)
+        }
+    }
+
+    /// Drop all pending sample data, keeping the settings
+    pub(crate) fn reset(&mut self) {
+        self.buf.fill(0);
+        self.tbuf.fill(0);
+        self.bhead = 0;
+        self.btail = 0;
+        self.e_rem = 0;
+        self.samples_done = 0;
+        self.frames_out = 0;
+        self.clipped = 0;
+        self.filter.reset();
+        if let Some(limiter) = &mut self.limiter {
+            limiter.reset();
+        }
+        if let Some(meters) = &mut self.meters {
+            meters.reset();
+        }
+    }
+
+    /// Playback position in (per channel) samples
+    pub(crate) const fn position(&self) -> u64 {
+        self.frames_out
+    }
+
+    pub(crate) const fn is_drained(&self) -> bool {
+        available_sound_data(self) == 0
+    }
+
+    pub(crate) const fn set_pan(&mut self, voice: usize, pan: i8) {
+        self.pans[voice] = pan;
+    }
+
+    pub(crate) const fn set_pans(&mut self, pans: [i8; MAX_CHANNELS as usize]) {
+        self.pans = pans;
+    }
+
+    pub(crate) const fn pans(&self) -> [i8; MAX_CHANNELS as usize] {
+        self.pans
+    }
+
+    pub(crate) const fn set_separation(&mut self, percent: u8) {
+        self.separation = percent;
+    }
+
+    pub(crate) const fn separation(&self) -> u8 {
+        self.separation
+    }
+
+    pub(crate) const fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    pub(crate) const fn filter_mut(&mut self) -> &mut OutputFilter {
+        &mut self.filter
     }
 
-    pub(crate) const fn is_blend_on(&self) -> bool {
-        self.blend
+    pub(crate) fn set_master_gain(&mut self, gain: f32) {
+        self.master_gain = (f64::from(gain.max(0.0)) * 65_536.0).round() as i64;
     }
+
+    pub(crate) fn set_limiter(&mut self, limiter: Option<Limiter>) {
+        self.limiter = limiter;
+    }
+
+    pub(crate) const fn clipped(&self) -> u64 {
+        self.clipped
+    }
+
+    pub(crate) fn set_meters(&mut self, meters: Option<MeterTap>) {
+        self.meters = meters;
+    }
+
+    pub(crate) fn meters(&self) -> Option<&[VoiceMeter; MAX_CHANNELS as usize]> {
+        self.meters.as_ref().map(MeterTap::meters)
+    }
+}
+
+/// The voices that get mixed
+fn mixed_voices(
+    multimode: bool,
+    ch_on: [bool; MAX_CHANNELS as usize],
+) -> impl Iterator<Item = usize> {
+    let voices: &[usize] = if multimode {
+        &[4, 5, 6, 7, 0, 1, 2]
+    } else {
+        &[3, 0, 1, 2]
+    };
+    voices.iter().copied().filter(move |&voice| ch_on[voice])
+}
+
+/// Gains of a voice on the left and on the right, where 256 is full volume
+const fn pan_gains(pan: i8) -> [i32; 2] {
+    let pan = if pan < -100 {
+        -100
+    } else if pan > 100 {
+        100
+    } else {
+        pan as i32
+    };
+    let right = (pan + 100) * 256 / 200;
+    [256 - right, right]
 }
 
 fn mixit(
@@ -56,87 +205,60 @@ fn mixit(
     smplbuf: &[i8],
     ch_on: [bool; MAX_CHANNELS as usize],
 ) {
-    if tfmx.multimode {
-        if ch_on[4] {
-            mix(
-                &mut tfmx.hdb[4],
-                iterations,
-                &mut audio.tbuf[tbuf_offset..],
-                smplbuf,
-                &mut tfmx.cdb,
-            );
-        }
-        if ch_on[5] {
-            mix(
-                &mut tfmx.hdb[5],
-                iterations,
-                &mut audio.tbuf[tbuf_offset..],
-                smplbuf,
-                &mut tfmx.cdb,
-            );
-        }
-        if ch_on[6] {
-            mix(
-                &mut tfmx.hdb[6],
-                iterations,
-                &mut audio.tbuf[tbuf_offset..],
-                smplbuf,
-                &mut tfmx.cdb,
-            );
-        }
-        if ch_on[7] {
-            mix(
-                &mut tfmx.hdb[7],
-                iterations,
-                &mut audio.tbuf[tbuf_offset..],
-                smplbuf,
-                &mut tfmx.cdb,
-            );
-        }
-    } else if ch_on[3] {
-        mix(
-            &mut tfmx.hdb[3],
-            iterations,
-            &mut audio.tbuf[tbuf_offset..],
-            smplbuf,
-            &mut tfmx.cdb,
-        );
-    }
-    if ch_on[0] {
+    for voice in mixed_voices(tfmx.multimode, ch_on) {
+        let vbuf = &mut audio.vbuf[..iterations];
+        vbuf.fill(0);
         mix(
-            &mut tfmx.hdb[0],
+            &mut tfmx.hdb[voice],
             iterations,
-            &mut audio.tbuf[tbuf_offset..],
+            vbuf,
             smplbuf,
             &mut tfmx.cdb,
+            audio.interpolation,
         );
+        if let Some(meters) = &mut audio.meters {
+            meters.feed(voice, tbuf_offset, vbuf);
+        }
+        let [left, right] = pan_gains(audio.pans[voice]);
+        let (tbuf_left, tbuf_right) = audio.tbuf.split_at_mut(HALFBUFSIZE);
+        for (i, &sample) in vbuf.iter().enumerate() {
+            tbuf_left[tbuf_offset + i] += (sample * left) >> 8;
+            tbuf_right[tbuf_offset + i] += (sample * right) >> 8;
+        }
     }
-    if ch_on[1] {
-        mix(
-            &mut tfmx.hdb[1],
-            iterations,
-            &mut audio.tbuf[HALFBUFSIZE + tbuf_offset..],
-            smplbuf,
-            &mut tfmx.cdb,
-        );
+}
+
+/// Like [`mixit`], but only advance the voices without producing any output
+fn skipit(
+    iterations: usize,
+    tfmx: &mut TfmxCtx,
+    smplbuf: &[i8],
+    ch_on: [bool; MAX_CHANNELS as usize],
+) {
+    for voice in mixed_voices(tfmx.multimode, ch_on) {
+        skip(&mut tfmx.hdb[voice], iterations, smplbuf, &mut tfmx.cdb);
     }
-    if ch_on[2] {
-        mix(
-            &mut tfmx.hdb[2],
-            iterations,
-            &mut audio.tbuf[HALFBUFSIZE + tbuf_offset..],
-            smplbuf,
-            &mut tfmx.cdb,
-        );
+}
+
+/// Run the output through the emulated Amiga filters
+fn apply_filter(audio: &mut AudioCtx) {
+    for i in 0..audio.samples_done {
+        for (ch, half) in [(0, 0), (1, HALFBUFSIZE)] {
+            let sample = &mut audio.tbuf[half + i];
+            *sample = audio.filter.process(ch, f64::from(*sample) as f32).round() as i32;
+        }
     }
 }
 
-/// Perform stereo blending to make headphone listening experience less weird
-fn stereo_blend(audio: &mut AudioCtx) {
+/// Mix the channels into each other, to make headphone listening experience less weird
+fn apply_separation(audio: &mut AudioCtx) {
+    // Gains of a channel in itself and in the other one, where 256 is full volume
+    let own = 128 + i32::from(audio.separation.min(100)) * 128 / 100;
+    let other = 256 - own;
     for i in 0..audio.samples_done {
         let buf = &mut audio.tbuf[i..];
-        let y = ((buf[HALFBUFSIZE] * 11) + ((buf[0]) * 5)) >> 4;
-        buf[0] = ((buf[HALFBUFSIZE] * 5) + ((buf[0]) * 11)) >> 4;
+        let y = ((buf[HALFBUFSIZE] * own) + ((buf[0]) * other)) >> 8;
+        buf[0] = ((buf[HALFBUFSIZE] * other) + ((buf[0]) * own)) >> 8;
         buf[HALFBUFSIZE] = y;
     }
 }
@@ -148,22 +270,56 @@ fn conv_s16(ctx: &mut AudioCtx) {
     // filled half so abort in this case. We could wait here instead.
     assert!(available_sound_data(ctx) + (num * ctx.multiplier) < BUFSIZE);
 
-    if ctx.blend {
-        stereo_blend(ctx);
+    if ctx.filter.is_active() {
+        apply_filter(ctx);
+    }
+    if ctx.separation < 100 {
+        apply_separation(ctx);
     }
 
-    let buf = &mut ctx.buf[ctx.bhead..];
     for i in 0..num {
-        buf[i * 2] = ctx.tbuf[i + HALFBUFSIZE] as i16;
-        buf[i * 2 + 1] = ctx.tbuf[i] as i16;
+        // Left first, like WAV files and audio APIs expect
+        let mut frame = [ctx.tbuf[i], ctx.tbuf[i + HALFBUFSIZE]];
         ctx.tbuf[i] = 0;
         ctx.tbuf[i + HALFBUFSIZE] = 0;
+        if ctx.master_gain != UNITY_GAIN {
+            frame = frame.map(|s| {
+                ((i64::from(s) * ctx.master_gain) >> 16).clamp(i32::MIN.into(), i32::MAX.into())
+                    as i32
+            });
+        }
+        match ctx.limiter.as_mut().map(|limiter| limiter.process(frame)) {
+            Some(Some(limited)) => put_frame(ctx, limited.map(|s| s.round() as i32)),
+            Some(None) => {}
+            None => put_frame(ctx, frame),
+        }
+    }
+}
+
+/// Output the frames still held back by the limiter, at the end of the song
+fn flush_limiter(ctx: &mut AudioCtx) {
+    let Some(limiter) = &mut ctx.limiter else {
+        return;
+    };
+    let frames = limiter.flush();
+    assert!(available_sound_data(ctx) + (frames.len() * ctx.multiplier) < BUFSIZE);
+    for frame in frames {
+        put_frame(ctx, frame.map(|s| s.round() as i32));
     }
+}
 
-    ctx.bhead = (ctx.bhead + (num * ctx.multiplier)) % BUFSIZE;
+/// Append a frame to the output, counting the samples beyond 16 bit full scale
+fn put_frame(ctx: &mut AudioCtx, frame: [i32; 2]) {
+    for (i, sample) in frame.into_iter().enumerate() {
+        if i16::try_from(sample).is_err() {
+            ctx.clipped += 1;
+        }
+        ctx.buf[(ctx.bhead + i) % BUFSIZE] = sample;
+    }
+    ctx.bhead = (ctx.bhead + ctx.multiplier) % BUFSIZE;
 }
 
-pub(crate) fn try_to_makeblock(
+fn try_to_makeblock(
     header: &Header,
     audio: &mut AudioCtx,
     tfmx: &mut TfmxCtx,
@@ -173,65 +329,224 @@ pub(crate) fn try_to_makeblock(
     let mut r = 0;
 
     while available_sound_data(audio) < BUFSIZE / 2 && tfmx.mdb.player_enable {
-        const WHAT: usize = 357_955;
-
-        tfmx_irq_in(header, tfmx);
-        let mut nb = (tfmx.e_clocks * (tfmx.out_rate >> 1)) as usize;
-        audio.e_rem += nb % WHAT;
-        nb /= WHAT;
-        if audio.e_rem > WHAT {
-            nb += 1;
-            audio.e_rem -= WHAT;
+        let nb = run_tick(header, tfmx, &mut audio.e_rem);
+        r += mix_samples(nb, audio, tfmx, smplbuf, ch_on);
+    }
+
+    tfmx.mdb.player_enable.then_some(r)
+}
+
+/// Sample data of each voice, for rendering stems
+pub(crate) type VoiceBufs = [Vec<i32>; MAX_CHANNELS as usize];
+
+/// Run the song for up to `len` samples, mixing every voice on its own instead of
+/// into the stereo output.
+///
+/// `sink` is called after each tick with the position and the samples of the tick,
+/// for every voice. Voices that aren't playing get silence.
+/// Returns the number of samples rendered, which is less than `len` if the song ended.
+pub(crate) fn render_voices<E>(
+    header: &Header,
+    tfmx: &mut TfmxCtx,
+    smplbuf: &[i8],
+    interpolation: Interpolation,
+    len: u64,
+    mut sink: impl FnMut(u64, &VoiceBufs) -> Result<(), E>,
+) -> Result<u64, E> {
+    let mut bufs = VoiceBufs::default();
+    let mut e_rem = 0;
+    let mut pos = 0;
+    while pos < len && tfmx.mdb.player_enable {
+        let nb = run_tick(header, tfmx, &mut e_rem);
+        let nb = nb.min((len - pos).try_into().unwrap_or(usize::MAX));
+        for buf in &mut bufs {
+            buf.clear();
+            buf.resize(nb, 0);
         }
-        while nb > 0 {
-            let mut n = audio.blocksize - audio.samples_done;
-            if n > nb {
-                n = nb;
-            }
-            mixit(n, audio.samples_done, tfmx, audio, smplbuf, ch_on);
-            audio.samples_done += n;
-            nb -= n;
-
-            // convert full blocksize or partial block at end of player
-            if audio.samples_done == audio.blocksize || !tfmx.mdb.player_enable {
-                conv_s16(audio);
-                audio.samples_done = 0;
-                r += 1;
-            }
+        for voice in mixed_voices(tfmx.multimode, [true; MAX_CHANNELS as usize]) {
+            mix(
+                &mut tfmx.hdb[voice],
+                nb,
+                &mut bufs[voice],
+                smplbuf,
+                &mut tfmx.cdb,
+                interpolation,
+            );
         }
+        sink(pos, &bufs)?;
+        pos += nb as u64;
     }
+    Ok(pos)
+}
 
-    tfmx.mdb.player_enable.then_some(r)
+/// Mix `nb` samples, converting each block as it fills up.
+///
+/// Returns the number of blocks converted.
+fn mix_samples(
+    mut nb: usize,
+    audio: &mut AudioCtx,
+    tfmx: &mut TfmxCtx,
+    smplbuf: &[i8],
+    ch_on: [bool; MAX_CHANNELS as usize],
+) -> u32 {
+    let mut r = 0;
+    while nb > 0 {
+        let mut n = audio.blocksize - audio.samples_done;
+        if n > nb {
+            n = nb;
+        }
+        mixit(n, audio.samples_done, tfmx, audio, smplbuf, ch_on);
+        audio.samples_done += n;
+        nb -= n;
+
+        if audio.samples_done == audio.blocksize {
+            finish_block(audio, tfmx);
+            r += 1;
+        }
+    }
+    if !tfmx.mdb.player_enable {
+        // convert the partial block at end of player
+        if audio.samples_done > 0 {
+            finish_block(audio, tfmx);
+            r += 1;
+        }
+        flush_limiter(audio);
+    }
+    r
 }
 
-const fn available_sound_data(ctx: &AudioCtx) -> usize {
-    let l = ctx.bhead.abs_diff(ctx.btail) + BUFSIZE;
-    l % BUFSIZE
+/// Convert the mixed samples of the current block
+fn finish_block(audio: &mut AudioCtx, tfmx: &TfmxCtx) {
+    if let Some(meters) = &mut audio.meters {
+        meters.finish_block(audio.samples_done, &tfmx.hdb, &tfmx.cdb);
+    }
+    conv_s16(audio);
+    audio.samples_done = 0;
 }
 
-pub(crate) fn present_output(
-    player: &mut TfmxPlayer,
-    ctx: &mut AudioCtx,
-    mut handler: impl crate::NewDataFn,
-) -> NewDataCtlFlow {
-    let mut total_len = available_sound_data(ctx);
-    let mut cmd = None;
-    while total_len > 0 {
-        let mut len = total_len;
-        if ctx.btail + len > BUFSIZE {
-            len = BUFSIZE - ctx.btail;
+/// Run the song for `target` samples without mixing them, so rendering continues from there.
+///
+/// The part of the last tick that lies past `target` is mixed as usual.
+/// Returns the number of samples skipped, which is less than `target` if the song
+/// ended before it.
+pub(crate) fn fast_forward(
+    header: &Header,
+    audio: &mut AudioCtx,
+    tfmx: &mut TfmxCtx,
+    smplbuf: &[i8],
+    ch_on: [bool; MAX_CHANNELS as usize],
+    target: u64,
+) -> u64 {
+    let mut done = 0;
+    while done < target && tfmx.mdb.player_enable {
+        let nb = run_tick(header, tfmx, &mut audio.e_rem);
+        let n = nb.min((target - done).try_into().unwrap_or(usize::MAX));
+        skipit(n, tfmx, smplbuf, ch_on);
+        done += n as u64;
+        if n < nb {
+            mix_samples(nb - n, audio, tfmx, smplbuf, ch_on);
         }
-        let end_idx = (ctx.bhead + len).min(ctx.buf.len());
-        cmd = handler(&ctx.buf[ctx.bhead..end_idx], player)?;
+    }
+    audio.frames_out = done;
+    done
+}
+
+/// Run the player for one tick, returning the number of samples it lasts.
+///
+/// The fractional part is carried over between ticks in `e_rem`. A tick that ends the
+/// song because it would start another pass lasts no samples.
+pub(crate) fn run_tick(header: &Header, tfmx: &mut TfmxCtx, e_rem: &mut usize) -> usize {
+    tfmx_irq_in(header, tfmx);
+    let frame = tfmx.loop_tracker.clock;
+    for kind in tfmx.idb.take_events() {
+        tfmx.events.push(frame, kind);
+    }
+    if tfmx.loop_tracker.ended {
+        return 0;
+    }
+    let nb = tick_samples(tfmx, e_rem);
+    tfmx.loop_tracker.clock += nb as u64;
+    nb
+}
+
+/// Number of samples that the tick that was just run lasts
+const fn tick_samples(tfmx: &TfmxCtx, e_rem: &mut usize) -> usize {
+    const WHAT: usize = 357_955;
 
-        ctx.btail = (ctx.btail + len) % BUFSIZE;
+    let mut nb = tfmx.e_clocks as usize * (tfmx.out_rate >> 1) as usize;
+    *e_rem += nb % WHAT;
+    nb /= WHAT;
+    if *e_rem > WHAT {
+        nb += 1;
+        *e_rem -= WHAT;
+    }
+    nb
+}
+
+const fn available_sound_data(ctx: &AudioCtx) -> usize {
+    (ctx.bhead + BUFSIZE - ctx.btail) % BUFSIZE
+}
 
-        total_len -= len;
+/// Fill `out` with rendered sample data in `layout`, making new blocks as needed.
+///
+/// Returns the number of samples written, which is less than `out.len()` if the song ended.
+/// Only whole frames are written.
+pub(crate) fn render<S: Sample>(
+    header: &Header,
+    audio: &mut AudioCtx,
+    tfmx: &mut TfmxCtx,
+    smplbuf: &[i8],
+    ch_on: [bool; MAX_CHANNELS as usize],
+    layout: ChannelLayout,
+    out: &mut [S],
+) -> usize {
+    let wanted = out.len() / layout.channels();
+    let mut frames = 0;
+    while frames < wanted {
+        if available_sound_data(audio) == 0 {
+            try_to_makeblock(header, audio, tfmx, smplbuf, ch_on);
+        }
+        let avail = available_sound_data(audio) / 2;
+        if avail == 0 {
+            break;
+        }
+        let end = frames + avail.min(wanted - frames);
+        for frame in frames..end {
+            let first = audio.buf[audio.btail];
+            let second = audio.buf[audio.btail + 1];
+            audio.btail = (audio.btail + 2) % BUFSIZE;
+            match layout {
+                ChannelLayout::Interleaved => {
+                    out[frame * 2] = S::from_mix(first);
+                    out[frame * 2 + 1] = S::from_mix(second);
+                }
+                ChannelLayout::Planar => {
+                    out[frame] = S::from_mix(first);
+                    out[wanted + frame] = S::from_mix(second);
+                }
+                ChannelLayout::Mono => {
+                    out[frame] = S::from_mix(((i64::from(first) + i64::from(second)) >> 1) as i32);
+                }
+            }
+        }
+        frames = end;
     }
-    ControlFlow::Continue(cmd)
+    if layout == ChannelLayout::Planar && frames < wanted {
+        // Close the gap between the channels
+        out.copy_within(wanted..wanted + frames, frames);
+    }
+    audio.frames_out += frames as u64;
+    frames * layout.channels()
 }
 
-fn mix(hw: &mut Hdb, iterations: usize, out_buf: &mut [i32], smplbuf: &[i8], cdb_arr: &mut CdbArr) {
+fn mix(
+    hw: &mut Hdb,
+    iterations: usize,
+    out_buf: &mut [i32],
+    smplbuf: &[i8],
+    cdb_arr: &mut CdbArr,
+    interpolation: Interpolation,
+) {
     if hw.sample_start >= smplbuf.len() {
         log::error!(
             "mix_add_ov: sample_start out of bounds: {}",
@@ -276,14 +591,34 @@ fn mix(hw: &mut Hdb, iterations: usize, out_buf: &mut [i32], smplbuf: &[i8], cdb
     for sample in out_buf.iter_mut().take(iterations) {
         let pos_real = pos >> FRACTION_BITS;
         let v1 = i32::from(p[pos_real as usize]);
-        let v2 = if pos_real + 1 < u32::from(hw.slen) {
-            i32::from(p[pos_real as usize + 1])
-        } else {
-            i32::from(smplbuf[hw.sample_start])
+        *sample += match interpolation {
+            Interpolation::Nearest => volume * v1,
+            Interpolation::Linear => {
+                let v2 = if pos_real + 1 < u32::from(hw.slen) {
+                    i32::from(p[pos_real as usize + 1])
+                } else {
+                    i32::from(smplbuf[hw.sample_start])
+                };
+                let base_sample =
+                    v1 + (((v2 - v1) * (pos & u32::from(FRACTION_MASK)) as i32) >> FRACTION_BITS);
+                volume * base_sample
+            }
+            Interpolation::Cubic | Interpolation::Sinc => {
+                let loop_part = smplbuf
+                    .get(hw.sample_start..hw.sample_start + hw.sample_len as usize)
+                    .unwrap_or_default();
+                let point = |offset: isize| {
+                    let idx = (pos_real as usize).saturating_add_signed(offset);
+                    p.get(idx)
+                        .or_else(|| loop_part.get(idx - p.len()))
+                        .map_or(0.0, |&v| f32::from(v))
+                };
+                let frac = f32::from((pos & u32::from(FRACTION_MASK)) as u16) / FRACTION_ONE;
+                let step = (f64::from(delta) / f64::from(FRACTION_ONE)) as f32;
+                let value = interpolation.filter(point, frac, step);
+                (f32::from(volume as u8) * value).round() as i32
+            }
         };
-        let base_sample =
-            v1 + (((v2 - v1) * (pos & u32::from(FRACTION_MASK)) as i32) >> FRACTION_BITS);
-        *sample += volume * base_sample;
         pos += delta;
 
         if pos < len {
@@ -309,6 +644,62 @@ fn mix(hw: &mut Hdb, iterations: usize, out_buf: &mut [i32], smplbuf: &[i8], cdb
     }
 }
 
+/// Advance a voice like [`mix`] does, without reading any sample data
+fn skip(hw: &mut Hdb, iterations: usize, smplbuf: &[i8], cdb_arr: &mut CdbArr) {
+    if hw.sample_start >= smplbuf.len() {
+        hw.sample_start = 0;
+    }
+    let mut pos = u64::from(hw.pos);
+    let mut delta = u64::from(hw.delta);
+    let mut len = u64::from(hw.slen) << FRACTION_BITS;
+
+    if (((hw.mode) & 1) == 0) || (len < 0x10000) {
+        return;
+    }
+    if (hw.mode & 3) == 1 {
+        hw.sbeg = hw.sample_start;
+        hw.slen = hw.sample_len;
+        len = u64::from(hw.sample_len) << FRACTION_BITS;
+        pos = 0;
+        hw.mode |= 2;
+    }
+
+    let mut left = iterations as u64;
+    while left > 0 {
+        // How many samples until the end of the sample is reached
+        let until_end = if pos >= len {
+            1
+        } else if delta == 0 {
+            u64::MAX
+        } else {
+            (len - pos).div_ceil(delta)
+        };
+        if until_end > left {
+            pos += delta * left;
+            break;
+        }
+        left -= until_end;
+        pos = pos + delta * until_end - len;
+        hw.sbeg = hw.sample_start;
+        hw.slen = hw.sample_len;
+        len = u64::from(hw.sample_len) << FRACTION_BITS;
+        if (len < 0x10000) || ((hw.loop_fn)(hw, cdb_arr) == 0) {
+            delta = 0;
+            pos = 0;
+            hw.slen = 0;
+            hw.sbeg = 0;
+            break;
+        }
+    }
+    hw.pos = pos as u32;
+    hw.delta = delta as u32;
+    if (hw.mode & 4) != 0 {
+        (hw.mode = 0);
+    }
+}
+
 const FRACTION_BITS: u8 = 14;
 const INTEGER_MASK: u32 = 0xFFFF_FFFF << FRACTION_BITS;
 const FRACTION_MASK: u16 = (!INTEGER_MASK) as u16;
+/// 1.0 in the fixed point format of sample positions
+const FRACTION_ONE: f32 = 16_384.0;
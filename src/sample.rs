@@ -0,0 +1,49 @@
+/// How the samples of the two output channels are arranged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChannelLayout {
+    /// Stereo frames, left channel then right channel
+    #[default]
+    Interleaved,
+    /// All samples of the left channel, followed by the same number of samples of the
+    /// right channel
+    Planar,
+    /// Both channels mixed down into one
+    Mono,
+}
+
+impl ChannelLayout {
+    /// Number of samples per frame
+    #[must_use]
+    pub const fn channels(self) -> usize {
+        match self {
+            Self::Interleaved | Self::Planar => 2,
+            Self::Mono => 1,
+        }
+    }
+}
+
+/// A type the player can output samples as
+pub trait Sample: Copy + Default {
+    /// Convert a sample of the mix, where 16 bit full scale is 32768, saturating to the
+    /// range of the type
+    fn from_mix(sample: i32) -> Self;
+}
+
+impl Sample for i16 {
+    fn from_mix(sample: i32) -> Self {
+        sample.clamp(Self::MIN.into(), Self::MAX.into()) as Self
+    }
+}
+
+impl Sample for i32 {
+    fn from_mix(sample: i32) -> Self {
+        (i64::from(sample) << 16).clamp(Self::MIN.into(), Self::MAX.into()) as Self
+    }
+}
+
+/// Full scale is 1.0, and samples beyond it are kept as they are
+impl Sample for f32 {
+    fn from_mix(sample: i32) -> Self {
+        (f64::from(sample) / 32_768.0) as Self
+    }
+}
@@ -1,5 +1,8 @@
 use {
-    crate::{CdbArr, EditBuf, HdbArr, MAX_CHANNELS, SongIdx, TfmxCtx, header::Header},
+    crate::{
+        CdbArr, EditBuf, HdbArr, MAX_CHANNELS, SongIdx, TfmxCtx, events::EventKind, header::Header,
+        looping::LoopTracker,
+    },
     std::cmp::Ordering,
     u32be::U32Be,
 };
@@ -54,7 +57,7 @@ impl TfmxCtx {
             c.sfx_flag = 0;
             c.cur_vol = 0;
             c.sfx_flag = 0;
-            c.sfx_code = 0;
+            c.sfx_code = None;
             c.save_addr = 0;
             c.loop_ = -1;
             c.new_style_macro = u8::MAX;
@@ -221,7 +224,14 @@ fn run_macro(
                 *word.byte_mut::<0>() = c.curr_note;
                 *word.byte_mut::<2>() =
                     (i32::from(word.byte::<2>()) | i32::from(c.velocity) << 4) as u8;
-                note_port(word.whole(), cdb_arr, multimode, danger_freak_hack, macros);
+                note_port(
+                    word.whole(),
+                    cdb_arr,
+                    multimode,
+                    danger_freak_hack,
+                    macros,
+                    idb,
+                );
                 continue;
             }
             31 => Action::CPeriod(c.prev_note),
@@ -311,7 +321,7 @@ fn run_macro(
                 continue;
             }
             32 => {
-                idb.cue[(i32::from(word.byte::<1>()) & 0x3) as usize] = word.hi();
+                idb.set_cue(word.byte::<1>() & 0x3, word.hi());
                 continue;
             }
             34 => {
@@ -379,22 +389,16 @@ fn run_macro(
 fn get_track_step(
     track_start: usize,
     pdblk: &mut Pdblk,
-    loops: &mut i32,
+    loop_tracker: &mut LoopTracker,
     jiffies: &mut i32,
     mdb: &mut Mdb,
     e_clocks: &mut u32,
     editbuf: &EditBuf,
     multimode: &mut bool,
     patterns_idx: usize,
+    idb: &mut Idb,
 ) {
     loop {
-        if pdblk.curr_pos == pdblk.first_pos && *loops <= 0 {
-            if *loops < 0 {
-                mdb.player_enable = false;
-                return;
-            }
-            *loops -= 1;
-        }
         let l: &[u16] = bytemuck::cast_slice(
             &editbuf[track_start.wrapping_add(usize::from(pdblk.curr_pos) * 4)..],
         );
@@ -406,13 +410,6 @@ fn get_track_step(
                     return;
                 }
                 1 => {
-                    if *loops != 0 {
-                        *loops -= 1;
-                        if *loops == 0 {
-                            mdb.player_enable = false;
-                            return;
-                        }
-                    }
                     let track_loop = mdb.track_loop;
                     mdb.track_loop -= 1;
                     if track_loop == 0 {
@@ -428,14 +425,15 @@ fn get_track_step(
                 2 => {
                     pdblk.prescale = l[2];
                     mdb.speed_cnt = pdblk.prescale;
-                    let x;
-                    if l[3] & 0xf200 == 0 && {
-                        x = i32::from(i32::from(l[3]) & 0x1ff > 0xf);
-                        x != 0
-                    } {
-                        *e_clocks = (0x001b_51f8 / x) as u32;
+                    let bpm = i32::from(l[3]) & 0x1ff;
+                    if l[3] & 0xf200 == 0 && bpm > 0xf {
+                        *e_clocks = (0x001b_51f8 / bpm) as u32;
                         mdb.cia_save = *e_clocks as u16;
                     }
+                    idb.push_event(EventKind::TempoChange {
+                        speed: pdblk.prescale,
+                        e_clocks: *e_clocks,
+                    });
                     pdblk.curr_pos = pdblk.curr_pos.wrapping_add(1);
                 }
                 3 => {
@@ -449,6 +447,10 @@ fn get_track_step(
                         *e_clocks = (14318 * (x + 100) / 100) as u32;
                         mdb.cia_save = *e_clocks as u16;
                         *multimode = true;
+                        idb.push_event(EventKind::TempoChange {
+                            speed: pdblk.prescale,
+                            e_clocks: *e_clocks,
+                        });
                     }
                     pdblk.curr_pos = pdblk.curr_pos.wrapping_add(1);
                 }
@@ -461,7 +463,14 @@ fn get_track_step(
                 }
             }
         } else {
-            for (pdb, l) in pdblk.p.iter_mut().zip(l) {
+            if loop_tracker.step_fetched(pdblk, mdb.track_loop) {
+                mdb.player_enable = false;
+                return;
+            }
+            idb.push_event(EventKind::TrackStep {
+                position: pdblk.curr_pos,
+            });
+            for (voice, (pdb, l)) in pdblk.p.iter_mut().zip(l).enumerate() {
                 pdb.xpose = (l & 0xff) as i8;
                 pdb.num = (l >> 8) as u8;
                 let pat_idx = pdb.num;
@@ -471,6 +480,11 @@ fn get_track_step(
                     pdb.loop_ = 0xffff;
                     let patterns = &editbuf[patterns_idx..];
                     pdb.addr = patterns[usize::from(pat_idx)];
+                    idb.push_event(EventKind::PatternChange {
+                        voice: voice as u8,
+                        pattern: pat_idx,
+                        transpose: pdb.xpose,
+                    });
                 }
             }
             return;
@@ -488,7 +502,7 @@ fn do_track(
     danger_freak_hack: bool,
     macros: &[u32],
     pdb: &mut Pdblk,
-    loops: &mut i32,
+    loop_tracker: &mut LoopTracker,
     jiffies: &mut i32,
     mdb: &mut Mdb,
     e_clocks: &mut u32,
@@ -532,7 +546,14 @@ fn do_track(
                 };
             }
             {
-                note_port(word.whole(), cdb, *multimode, danger_freak_hack, macros);
+                note_port(
+                    word.whole(),
+                    cdb,
+                    *multimode,
+                    danger_freak_hack,
+                    macros,
+                    idb,
+                );
             }
             if (t & 0xC0) == 0x80 {
                 return false;
@@ -555,13 +576,14 @@ fn do_track(
                 get_track_step(
                     track_start,
                     pdb,
-                    loops,
+                    loop_tracker,
                     jiffies,
                     mdb,
                     e_clocks,
                     editbuf,
                     multimode,
                     patterns_idx,
+                    idb,
                 );
                 return true;
             }
@@ -613,7 +635,14 @@ fn do_track(
 
             5 | 6 | 7 | 12 => {
                 // Kup^ | Vibr | Enve | Lock
-                note_port(word.whole(), cdb, *multimode, danger_freak_hack, macros);
+                note_port(
+                    word.whole(),
+                    cdb,
+                    *multimode,
+                    danger_freak_hack,
+                    macros,
+                    idb,
+                );
             }
 
             9 => {
@@ -633,7 +662,7 @@ fn do_track(
 
             13 => {
                 // Cue
-                idb.cue[(word.byte::<1>() & 0x03) as usize] = word.hi();
+                idb.set_cue(word.byte::<1>() & 0x03, word.hi());
             }
 
             11 => {
@@ -645,6 +674,11 @@ fn do_track(
                 pdb.p[t as usize].step = 0;
                 pdb.p[t as usize].wait = 0;
                 pdb.p[t as usize].loop_ = 0xFFFF;
+                idb.push_event(EventKind::PatternChange {
+                    voice: t,
+                    pattern: word.byte::<1>(),
+                    transpose: word.byte::<3>() as i8,
+                });
             }
             // We covered all possible values for the bitmask
             _ => unreachable!(),
@@ -660,15 +694,22 @@ static NOTEVALS: [u16; 64] = [
     0x0AA, 0x0A0, 0x097, 0x08F, 0x087, 0x07F, 0x078, 0x071, 0x0D6, 0x0CA, 0x0BF, 0x0B4,
 ];
 
+/// Amiga period of a note
+pub(crate) fn note_period(note: u8) -> u16 {
+    NOTEVALS[usize::from(note & 0x3F)]
+}
+
 fn note_port(
     i: u32,
     cdb_arr: &mut CdbArr,
     multimode: bool,
     danger_freak_hack: bool,
     macros: &[u32],
+    idb: &mut Idb,
 ) {
     let word = U32Be::from_ne(i);
-    let c = &mut cdb_arr[(word.byte::<2>() & (if multimode { 7 } else { 3 })) as usize];
+    let channel = word.byte::<2>() & (if multimode { 7 } else { 3 });
+    let c = &mut cdb_arr[channel as usize];
     if word.byte::<0>() == 0xFC {
         /* lock */
         c.sfx_flag = word.byte::<1>();
@@ -700,6 +741,13 @@ fn note_port(
         c.key_up = 1;
         c.loop_ = -1;
         c.macro_run = -1;
+        idb.push_event(EventKind::NoteOn {
+            channel,
+            note: c.curr_note & 0x3F,
+            macro_num: word.byte::<1>(),
+            velocity: c.velocity,
+            fine_tune: c.fine_tune as i8,
+        });
     } else if word.byte::<0>() < 0xF0 {
         c.porta_reset = word.byte::<1>();
         c.porta_time = 1;
@@ -709,6 +757,12 @@ fn note_port(
         c.porta_rate = i16::from(word.byte::<3>());
         c.curr_note = word.byte::<0>() & 0x3F;
         c.dest_period = NOTEVALS[c.curr_note as usize];
+        idb.push_event(EventKind::Portamento {
+            channel,
+            note: c.curr_note,
+            speed: word.byte::<1>(),
+            rate: word.byte::<3>(),
+        });
     } else {
         match word.byte::<0>() {
             0xF7 =>
@@ -718,6 +772,12 @@ fn note_port(
                 c.env_reset = (word.byte::<2>() >> 4) + 1;
                 c.env_time = (word.byte::<2>() >> 4) + 1;
                 c.env_end_vol = word.byte::<3>() as i8;
+                idb.push_event(EventKind::Envelope {
+                    channel,
+                    rate: c.env_rate,
+                    interval: c.env_reset,
+                    end_volume: c.env_end_vol,
+                });
             }
 
             0xF6 =>
@@ -728,18 +788,51 @@ fn note_port(
                 c.vib_width = word.byte::<3>() as i8;
                 c.vib_flag = 1; /* ?! */
                 c.vib_offset = 0;
+                idb.push_event(EventKind::Vibrato {
+                    channel,
+                    speed: c.vib_reset,
+                    depth: c.vib_width,
+                });
             }
 
             0xF5 =>
             /* kup^ */
             {
                 c.key_up = 0;
+                idb.push_event(EventKind::KeyUp { channel });
             }
             _ => todo!(),
         }
     }
 }
 
+/// Queue the sound effect note `code` on channel `ch`, for its next macro tick to play.
+///
+/// Returns false if the channel is locked, or has a note queued, with a higher priority.
+pub(crate) const fn queue_sfx(tfmx: &mut TfmxCtx, ch: usize, code: u32, priority: u8) -> bool {
+    let c = &mut tfmx.cdb[ch];
+    if (c.sfx_lock_time >= 0 || c.sfx_code.is_some()) && priority < c.sfx_priority {
+        return false;
+    }
+    c.sfx_code = Some(code);
+    c.sfx_priority = priority;
+    true
+}
+
+/// Keep other notes off channel `ch` for `ticks` macro ticks, like the lock note does.
+///
+/// Returns false if the channel is already locked with a higher priority.
+pub(crate) fn lock_channel(tfmx: &mut TfmxCtx, ch: usize, priority: u8, ticks: u8) -> bool {
+    let c = &mut tfmx.cdb[ch];
+    if c.sfx_lock_time >= 0 && priority < c.sfx_priority {
+        return false;
+    }
+    c.sfx_flag = priority.max(1);
+    c.sfx_priority = priority;
+    c.sfx_lock_time = i16::from(ticks);
+    true
+}
+
 fn do_effects(c: &mut Cdb, mdb: &mut Mdb) {
     let mut a: i32 = 0;
     if c.efx_run < 0 {
@@ -874,16 +967,15 @@ fn do_macro(cdb_idx: usize, macros_start: usize, tfmx: &mut TfmxCtx) {
         c.sfx_priority = 0;
     }
 
-    let sfx_code = c.sfx_code;
-    if sfx_code != 0 {
+    if let Some(sfx_code) = c.sfx_code.take() {
         c.sfx_flag = 0;
-        c.sfx_code = 0;
         note_port(
             sfx_code,
             cdb,
             multimode,
             danger_freak_hack,
             &editbuf[macros_start..],
+            idb,
         );
         let c = &mut cdb[cdb_idx];
         c.sfx_flag = c.sfx_priority;
@@ -932,7 +1024,7 @@ fn do_tracks(tfmx: &mut TfmxCtx, track_start: usize, macros_start: usize, patter
         danger_freak_hack,
         oops_up_hack,
         ref editbuf,
-        ref mut loops,
+        ref mut loop_tracker,
         ref mut mdb,
         ref mut cdb,
         pdblk: ref mut pdb,
@@ -964,7 +1056,7 @@ fn do_tracks(tfmx: &mut TfmxCtx, track_start: usize, macros_start: usize, patter
                 danger_freak_hack,
                 &editbuf[macros_start..],
                 pdb,
-                loops,
+                loop_tracker,
                 jiffies,
                 mdb,
                 e_clocks,
@@ -972,6 +1064,10 @@ fn do_tracks(tfmx: &mut TfmxCtx, track_start: usize, macros_start: usize, patter
                 idb,
                 hdb,
             ) {
+                // The song ended while going to the next track step
+                if !mdb.player_enable {
+                    break;
+                }
                 x = 0;
                 continue;
             }
@@ -1049,12 +1145,13 @@ pub(crate) fn tfmx_irq_in(header: &Header, tfmx: &mut TfmxCtx) {
 pub(crate) fn start_song(song: SongIdx, mode: i32, header: &Header, tfmx: &mut TfmxCtx) {
     let &mut TfmxCtx {
         ref editbuf,
-        ref mut loops,
+        ref mut loop_tracker,
         ref mut mdb,
         pdblk: ref mut pdb,
         ref mut jiffies,
         ref mut multimode,
         ref mut e_clocks,
+        ref mut idb,
         ..
     } = tfmx;
     mdb.player_enable = false; /* sort of locking mechanism */
@@ -1076,6 +1173,10 @@ pub(crate) fn start_song(song: SongIdx, mode: i32, header: &Header, tfmx: &mut T
         } else {
             pdb.prescale = tempo;
         }
+        idb.push_event(EventKind::TempoChange {
+            speed: pdb.prescale,
+            e_clocks: *e_clocks,
+        });
     }
     for pdb in &mut pdb.p {
         pdb.addr = 0;
@@ -1087,13 +1188,14 @@ pub(crate) fn start_song(song: SongIdx, mode: i32, header: &Header, tfmx: &mut T
         get_track_step(
             header.track_start,
             pdb,
-            loops,
+            loop_tracker,
             jiffies,
             mdb,
             e_clocks,
             editbuf,
             multimode,
             header.patt_start,
+            idb,
         );
     }
     mdb.end_flag = false;
@@ -1146,11 +1248,17 @@ pub(crate) struct Cdb {
     sfx_flag: u8,
     sfx_priority: u8,
     sfx_lock_time: i16,
-    sfx_code: u32,
+    /// Sound effect note waiting for the next macro tick. Every code is a valid note,
+    /// even 0.
+    sfx_code: Option<u32>,
     hw_idx: usize,
 }
 
 impl Cdb {
+    /// Period the channel currently plays at
+    pub(crate) const fn period(&self) -> u16 {
+        self.cur_period
+    }
     pub(crate) const fn default() -> Self {
         Self {
             macro_run: 0,
@@ -1196,19 +1304,40 @@ impl Cdb {
             sfx_flag: 0,
             sfx_priority: 0,
             sfx_lock_time: 0,
-            sfx_code: 0,
+            sfx_code: None,
             hw_idx: 0,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub(crate) struct Idb {
     cue: [u16; 4usize],
+    /// Events of the current tick, waiting to get their position
+    events: Vec<EventKind>,
 }
 impl Idb {
     pub(crate) const fn default() -> Self {
-        Self { cue: [0; 4] }
+        Self {
+            cue: [0; 4],
+            events: Vec::new(),
+        }
+    }
+    fn set_cue(&mut self, idx: u8, value: u16) {
+        if self.cue[idx as usize] != value {
+            self.cue[idx as usize] = value;
+            self.push_event(EventKind::CueChanged { index: idx, value });
+        }
+    }
+    pub(crate) const fn cues(&self) -> [u16; 4] {
+        self.cue
+    }
+    fn push_event(&mut self, kind: EventKind) {
+        self.events.push(kind);
+    }
+    /// Take the events since the last call
+    pub(crate) fn take_events(&mut self) -> std::vec::Drain<'_, EventKind> {
+        self.events.drain(..)
     }
 }
 
@@ -1225,7 +1354,7 @@ pub(crate) struct Mdb {
     fade_time: i8,
     fade_reset: i8,
     fade_slope: i8,
-    track_loop: i16,
+    pub(crate) track_loop: i16,
 }
 impl Mdb {
     pub(crate) const fn default() -> Self {
@@ -1276,8 +1405,8 @@ impl Pdb {
 pub(crate) struct Pdblk {
     first_pos: u16,
     last_pos: u16,
-    curr_pos: u16,
-    prescale: u16,
+    pub(crate) curr_pos: u16,
+    pub(crate) prescale: u16,
     pub(crate) p: PdbArr,
 }
 
@@ -0,0 +1,300 @@
+use {
+    crate::{
+        ChannelLayout, MAX_CHANNELS, SongIdx, TfmxPlayer,
+        duration::{self, MAX_ESTIMATE_SECS},
+        rendering::{self, CHUNK_LEN},
+    },
+    std::{
+        fs::File,
+        io::{self, BufWriter, Seek, SeekFrom, Write},
+        path::PathBuf,
+        time::Duration,
+    },
+};
+
+/// One mono buffer of samples for each hardware voice
+pub type Stems = [Vec<i16>; MAX_CHANNELS as usize];
+
+/// Settings for rendering subsongs to WAV files or stems
+#[derive(Debug, Clone)]
+pub struct WavOptions {
+    loop_count: u32,
+    max_length: Duration,
+    fade_out: Duration,
+}
+
+impl Default for WavOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WavOptions {
+    /// Play each song through once, without fading out
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            loop_count: 1,
+            max_length: Duration::from_secs(MAX_ESTIMATE_SECS),
+            fade_out: Duration::ZERO,
+        }
+    }
+    /// How many times to play a song through, 0 to play until [`Self::max_length`].
+    ///
+    /// Default is 1.
+    pub const fn loop_count(&mut self, count: u32) -> &mut Self {
+        self.loop_count = count;
+        self
+    }
+    /// Songs are cut off at this length. Default is an hour.
+    pub const fn max_length(&mut self, length: Duration) -> &mut Self {
+        self.max_length = length;
+        self
+    }
+    /// Keep playing for this long after the last pass, fading out.
+    ///
+    /// Songs that get cut off at [`Self::max_length`] fade out before it instead.
+    /// Songs that end on their own don't fade out. Default is no fade-out.
+    pub const fn fade_out(&mut self, length: Duration) -> &mut Self {
+        self.fade_out = length;
+        self
+    }
+}
+
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// How much of a song to render, and where to fade it out
+struct RenderSpan {
+    len: u64,
+    fade_start: u64,
+}
+
+impl RenderSpan {
+    fn new(player: &TfmxPlayer, idx: SongIdx, options: &WavOptions) -> Self {
+        let rate = player.tfmx.out_rate;
+        let max_len = duration::duration_to_samples(options.max_length, rate);
+        let fade_len = duration::duration_to_samples(options.fade_out, rate);
+        // Find out where the song ends, and whether it's because of looping
+        let sim = duration::run_song(
+            &player.header,
+            &player.clean_tfmx,
+            idx,
+            options.loop_count,
+            max_len,
+        );
+        let ends_on_its_own = !sim.mdb.player_enable
+            && (options.loop_count == 0 || sim.loop_tracker.passes < options.loop_count);
+        if ends_on_its_own {
+            let len = sim.loop_tracker.clock.min(max_len);
+            Self {
+                len,
+                fade_start: len,
+            }
+        } else {
+            let len = sim.loop_tracker.clock.saturating_add(fade_len).min(max_len);
+            Self {
+                len,
+                fade_start: len - fade_len.min(len),
+            }
+        }
+    }
+    /// Apply the fade-out to a sample at position `pos`
+    fn fade(&self, pos: u64, sample: i16) -> i16 {
+        if pos < self.fade_start {
+            return sample;
+        }
+        let left = (self.len - pos) as i64;
+        (i64::from(sample) * left / (self.len - self.fade_start) as i64) as i16
+    }
+}
+
+/// Writes a 16 bit WAV file, filling in the sizes once all samples are written
+struct WavWriter<W: Write + Seek> {
+    out: W,
+    riff_start: u64,
+    data_start: u64,
+    bytes: Vec<u8>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Write the headers, storing `comment` in a LIST/INFO chunk if it's not empty
+    fn new(mut out: W, rate: u32, channels: u16, comment: &str) -> io::Result<Self> {
+        let block_align = channels * BITS_PER_SAMPLE / 8;
+        let riff_start = out.stream_position()?;
+        out.write_all(b"RIFF\0\0\0\0WAVE")?;
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&rate.to_le_bytes())?;
+        out.write_all(&(rate * u32::from(block_align)).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        if !comment.is_empty() {
+            let info = info_subchunk(*b"ICMT", comment);
+            out.write_all(b"LIST")?;
+            out.write_all(&(4 + info.len() as u32).to_le_bytes())?;
+            out.write_all(b"INFO")?;
+            out.write_all(&info)?;
+        }
+        out.write_all(b"data\0\0\0\0")?;
+        let data_start = out.stream_position()?;
+        Ok(Self {
+            out,
+            riff_start,
+            data_start,
+            bytes: Vec::new(),
+        })
+    }
+    fn write_samples(&mut self, samples: impl Iterator<Item = i16>) -> io::Result<()> {
+        self.bytes.clear();
+        for sample in samples {
+            self.bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.out.write_all(&self.bytes)
+    }
+    /// Fill in the chunk sizes
+    fn finish(mut self) -> io::Result<W> {
+        let end = self.out.stream_position()?;
+        let too_long = |_| io::Error::new(io::ErrorKind::InvalidInput, "song too long for WAV");
+        let data_size = u32::try_from(end - self.data_start).map_err(too_long)?;
+        let riff_size = u32::try_from(end - self.riff_start - 8).map_err(too_long)?;
+        self.out.seek(SeekFrom::Start(self.data_start - 4))?;
+        self.out.write_all(&data_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(self.riff_start + 4))?;
+        self.out.write_all(&riff_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        Ok(self.out)
+    }
+}
+
+/// Render subsong `idx` of `player` to `out` as a stereo WAV file, returning how many
+/// samples clipped
+pub(crate) fn write_song<W: Write + Seek>(
+    player: &TfmxPlayer,
+    idx: SongIdx,
+    options: &WavOptions,
+    out: W,
+) -> io::Result<u64> {
+    let span = RenderSpan::new(player, idx, options);
+    let mut wav = WavWriter::new(out, player.tfmx.out_rate, 2, &comment(player))?;
+    // Render without a loop limit, so the fade-out can go past the last pass
+    let mut tfmx = player.clean_tfmx.start_copy(&player.header, idx, 0);
+    let mut audio = player.audio.new_like();
+    let mut buf = vec![0; CHUNK_LEN];
+    let mut pos: u64 = 0;
+    while pos < span.len {
+        let frames_left = usize::try_from(span.len - pos).unwrap_or(usize::MAX);
+        let want = buf.len().min(frames_left * 2);
+        let written = rendering::render(
+            &player.header,
+            &mut audio,
+            &mut tfmx,
+            &player.sample_buf,
+            player.ch_on,
+            ChannelLayout::Interleaved,
+            &mut buf[..want],
+        );
+        wav.write_samples(
+            buf[..written]
+                .iter()
+                .enumerate()
+                .map(|(i, &sample)| span.fade(pos + (i / 2) as u64, sample)),
+        )?;
+        pos += (written / 2) as u64;
+        if written < want {
+            break;
+        }
+    }
+    wav.finish()?;
+    Ok(audio.clipped())
+}
+
+/// Render every voice of subsong `idx` of `player` on its own, passing the samples
+/// of each tick to `sink`
+fn render_stems(
+    player: &TfmxPlayer,
+    idx: SongIdx,
+    options: &WavOptions,
+    mut sink: impl FnMut(usize, &mut dyn Iterator<Item = i16>) -> io::Result<()>,
+) -> io::Result<()> {
+    let span = RenderSpan::new(player, idx, options);
+    let mut tfmx = player.clean_tfmx.start_copy(&player.header, idx, 0);
+    rendering::render_voices(
+        &player.header,
+        &mut tfmx,
+        &player.sample_buf,
+        player.audio.interpolation(),
+        span.len,
+        |pos, bufs| -> io::Result<()> {
+            for (voice, buf) in bufs.iter().enumerate() {
+                let mut samples = buf
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &sample)| span.fade(pos + i as u64, sample as i16));
+                sink(voice, &mut samples)?;
+            }
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+/// Render every voice of subsong `idx` of `player` into its own buffer
+pub(crate) fn stem_buffers(player: &TfmxPlayer, idx: SongIdx, options: &WavOptions) -> Stems {
+    let mut stems = Stems::default();
+    render_stems(player, idx, options, |voice, samples| {
+        stems[voice].extend(samples);
+        Ok(())
+    })
+    .expect("Collecting into buffers doesn't fail");
+    stems
+}
+
+/// Render every voice of subsong `idx` of `player` to its own mono WAV file
+pub(crate) fn write_stems(
+    player: &TfmxPlayer,
+    idx: SongIdx,
+    options: &WavOptions,
+    mut path_for: impl FnMut(u8) -> PathBuf,
+) -> io::Result<()> {
+    let comment = comment(player);
+    let mut wavs = Vec::new();
+    for voice in 0..MAX_CHANNELS {
+        let file = BufWriter::new(File::create(path_for(voice))?);
+        wavs.push(WavWriter::new(file, player.tfmx.out_rate, 1, &comment)?);
+    }
+    render_stems(player, idx, options, |voice, samples| {
+        wavs[voice].write_samples(samples)
+    })?;
+    for wav in wavs {
+        wav.finish()?.flush()?;
+    }
+    Ok(())
+}
+
+/// The header text, one row per line
+fn comment(player: &TfmxPlayer) -> String {
+    player
+        .header
+        .text_rows()
+        .map(|row| row.trim_end_matches('\0').trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A subchunk of a LIST/INFO chunk, holding a NUL terminated string
+fn info_subchunk(id: [u8; 4], text: &str) -> Vec<u8> {
+    let mut data = text.as_bytes().to_vec();
+    data.push(0);
+    let mut chunk = Vec::with_capacity(data.len() + 9);
+    chunk.extend_from_slice(&id);
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(&data);
+    // Chunks are padded to an even size
+    if !data.len().is_multiple_of(2) {
+        chunk.push(0);
+    }
+    chunk
+}
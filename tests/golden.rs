@@ -0,0 +1,1336 @@
+//! Renders a small synthetic module and compares the output against known hashes
+
+use std::{sync::Arc, time::Duration};
+use tfmxr::{
+    AmigaModel, AssembleError, AssembleErrorKind, ChannelLayout, EventKind, FormatVariant,
+    Instrument, InstrumentFormat, Interpolation, Macro, MacroCommand, Module, ModuleInfo, Pattern,
+    PatternCommand, PlayerBuildError, PlayerBuilder, PlayerCmd, Sample, SfxError, SubsongKind,
+    TfmxPlayer, TrackCommand, WavOptions,
+};
+
+const TRACK_START: usize = 0x180;
+const PATT_START: usize = 0x80;
+const MACRO_START: usize = 0x100;
+
+/// Operand bytes to decode every command with
+const OPERANDS: [[u8; 3]; 6] = [
+    [0, 0, 0],
+    [1, 2, 3],
+    [0xFF, 0xFF, 0xFF],
+    [0x80, 0x40, 0x01],
+    [0x01, 0xFE, 0x30],
+    [0x7F, 0x0F, 0x80],
+];
+
+fn word(b0: u8, b1: u8, b2: u8, b3: u8) -> [u8; 4] {
+    [b0, b1, b2, b3]
+}
+
+/// A track step playing the given (pattern, transpose) pairs, leaving the other voices alone
+fn step(pats: &[(u8, u8)]) -> Vec<u8> {
+    (0..8)
+        .flat_map(|i| {
+            let (num, xpose) = pats.get(i).copied().unwrap_or((0xFF, 0));
+            [num, xpose]
+        })
+        .collect()
+}
+
+fn track_cmd(cmd: u16, a: u16, b: u16) -> Vec<u8> {
+    [0xeffe, cmd, a, b, 0, 0, 0, 0]
+        .iter()
+        .flat_map(|w: &u16| w.to_be_bytes())
+        .collect()
+}
+
+/// An .mdat with 5 songs that cover song ends, tempo changes, stop and loop commands,
+/// and 7 voice mode, along with its .smpl
+fn synthetic_module() -> (Vec<u8>, Vec<u8>) {
+    let tracks = [
+        // song 0
+        step(&[(0, 0), (1, 0)]),
+        step(&[(0, 12), (1, 0)]),
+        // song 1
+        step(&[(2, 0), (1, 0)]),
+        track_cmd(2, 3, 0),
+        step(&[(2, 5), (0x80, 0)]),
+        // song 2
+        step(&[(0, 0), (2, 0), (1, 0)]),
+        track_cmd(0, 0, 0),
+        // song 3, looping forever
+        step(&[(2, 0)]),
+        step(&[(0, 0), (1, 0)]),
+        track_cmd(1, 8, 1),
+        track_cmd(1, 8, 1),
+        // song 4
+        track_cmd(3, 0, 0),
+        step(&[
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (0, 7),
+            (1, 0),
+            (2, 5),
+            (0, 3),
+            (1, 2),
+        ]),
+    ];
+    let patterns = [
+        vec![
+            word(0x80 | 24, 0, 0xF0, 4),
+            word(0x80 | 28, 0, 0xF0, 4),
+            word(0x80 | 31, 1, 0xF0, 4),
+            word(0xF5, 0, 0, 0),
+            word(0xF3, 3, 0, 0),
+            word(0xF0, 0, 0, 0),
+        ],
+        vec![
+            word(0x80 | 12, 1, 0xC1, 8),
+            word(0xFD, 0, 0x12, 0x34),
+            word(0x80 | 19, 1, 0xC1, 8),
+            word(0xF0, 0, 0, 0),
+        ],
+        vec![
+            word(0x80 | 36, 2, 0xA2, 2),
+            word(0xC0 | 40, 4, 0xA2, 0x20),
+            word(0xF3, 5, 0, 0),
+            word(0x80 | 33, 2, 0xA2, 6),
+            word(0xF0, 0, 0, 0),
+        ],
+    ];
+    let macros = [
+        vec![
+            word(0, 0, 0, 0),
+            word(2, 0, 0, 0),
+            word(3, 0, 0, 16),
+            word(8, 0, 0, 0),
+            word(13, 0, 0, 0x10),
+            word(1, 0, 0, 0),
+            word(15, 2, 1, 0x10),
+            word(4, 0, 0, 16),
+            word(7, 0, 0, 0),
+        ],
+        vec![
+            word(0, 0, 0, 0),
+            word(2, 0, 0, 32),
+            word(3, 0, 0, 32),
+            word(8, 0, 0, 0),
+            word(12, 8, 0, 4),
+            word(14, 0, 0, 0x30),
+            word(1, 0, 0, 0),
+            word(32, 1, 0x0a, 0xbc),
+            word(4, 0, 0, 40),
+            word(7, 0, 0, 0),
+        ],
+        vec![
+            word(0, 0, 0, 0),
+            word(2, 0, 0, 0),
+            word(3, 0, 0, 16),
+            word(9, 12, 0, 0),
+            word(14, 0, 0, 0x38),
+            word(1, 0, 0, 0),
+            word(24, 0, 0, 8),
+            word(4, 0, 0, 4),
+            word(20, 0, 0, 3),
+            word(11, 1, 0, 8),
+            word(4, 0, 0, 20),
+            word(7, 0, 0, 0),
+        ],
+    ];
+    let mut data = vec![0; TRACK_START * 4];
+    data[PATT_START * 4..TRACK_START * 4].fill(0xFF);
+    data.extend(tracks.concat());
+    let mut add_items = |table: usize, items: &[Vec<[u8; 4]>]| {
+        for (i, item) in items.iter().enumerate() {
+            let addr = (0x200 + data.len()) as u32;
+            data[(table + i) * 4..(table + i + 1) * 4].copy_from_slice(&addr.to_be_bytes());
+            data.extend(item.concat());
+        }
+    };
+    add_items(PATT_START, &patterns);
+    add_items(MACRO_START, &macros);
+
+    let mut mdat = b"TFMX-SONG \0\0\0\0\0\0".to_vec();
+    let mut text = [0; 240];
+    text[..21].copy_from_slice(b"Synthetic test module");
+    text[40..61].copy_from_slice(b"by the test generator");
+    mdat.extend(text);
+    let song_table = |vals: [u16; 5]| {
+        let mut table = [0; 32];
+        table[..5].copy_from_slice(&vals);
+        table
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect::<Vec<u8>>()
+    };
+    mdat.extend(song_table([0, 2, 5, 7, 11]));
+    mdat.extend(song_table([1, 4, 6, 10, 12]));
+    mdat.extend(song_table([5, 4, 5, 3, 5]));
+    mdat.resize(0x200, 0);
+    mdat.extend(data);
+
+    let mut smpl = [64; 16].to_vec();
+    smpl.extend([192; 16]);
+    smpl.extend((0..64).map(|i: u8| (i * 4).wrapping_sub(128)));
+    (mdat, smpl)
+}
+
+fn player(configure: impl FnOnce(&mut PlayerBuilder)) -> TfmxPlayer {
+    let (mdat, smpl) = synthetic_module();
+    let mut builder = PlayerBuilder::from_bytes(mdat, smpl);
+    configure(&mut builder);
+    builder.build().unwrap()
+}
+
+fn render_song<S: Sample>(player: &mut TfmxPlayer, idx: u8) -> Vec<S> {
+    player.select_song(idx);
+    render_rest(player)
+}
+
+/// Render the current song from where it is to the end
+fn render_rest<S: Sample>(player: &mut TfmxPlayer) -> Vec<S> {
+    let mut out = Vec::new();
+    let mut buf = [S::default(); 4096];
+    loop {
+        let written = player.render(&mut buf);
+        out.extend_from_slice(&buf[..written]);
+        if written < buf.len() {
+            return out;
+        }
+    }
+}
+
+/// 64 bit FNV-1a
+fn hash(samples: &[i16]) -> u64 {
+    samples
+        .iter()
+        .flat_map(|s| s.to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |h, b| {
+            (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// (song, frames, hash) of the default output
+const GOLDEN: [(u8, usize, u64); 5] = [
+    (0, 190_509, 0x4381_ba06_2436_21e2),
+    (1, 77_614, 0xe055_e41f_f6de_e825),
+    (2, 85_552, 0x2b2e_ab06_6747_d720),
+    (3, 246_956, 0x46ab_e54d_62c5_f830),
+    (4, 84_670, 0x7ebb_da51_f43c_4b00),
+];
+
+#[test]
+fn default_output_unchanged() {
+    let mut player = player(|_| {});
+    for (idx, frames, expected) in GOLDEN {
+        let out = render_song(&mut player, idx);
+        assert_eq!(out.len() / 2, frames, "length of song {idx}");
+        assert_eq!(hash(&out), expected, "output of song {idx}");
+        assert_eq!(player.clipped_samples(), 0, "clipping in song {idx}");
+    }
+}
+
+#[test]
+fn module_info_describes_the_module() {
+    let (mdat, _) = synthetic_module();
+    let from_reader = ModuleInfo::from_reader(std::io::Cursor::new(mdat)).unwrap();
+    let from_player = player(|_| {}).module_info();
+    for info in [from_reader, from_player] {
+        assert_eq!(
+            info.text,
+            ["Synthetic test module", "by the test generator"]
+        );
+        let songs: Vec<_> = info
+            .subsongs
+            .iter()
+            .map(|song| (song.index, song.first_step, song.last_step, song.tempo))
+            .collect();
+        assert_eq!(
+            songs,
+            [
+                (0, 0, 1, 5),
+                (1, 2, 4, 4),
+                (2, 5, 6, 5),
+                (3, 7, 10, 3),
+                (4, 11, 12, 5)
+            ]
+        );
+        assert_eq!(info.macro_count, 3);
+        assert_eq!(info.pattern_count, 3);
+        // Song 4 switches to 7 voice mode with a tempo command
+        assert!(info.seven_voice);
+        assert_eq!(info.variant, FormatVariant::Unchecked);
+    }
+}
+
+#[test]
+fn subsongs_are_discovered() {
+    let (mut mdat, smpl) = synthetic_module();
+    // Slot 5 plays song 0 again, and slot 6 starts at the step that stops the song
+    for (table, [slot_5, slot_6]) in [(0x100, [0, 6]), (0x140, [1, 6]), (0x180, [5, 5])] {
+        mdat[table + 10..table + 14].copy_from_slice(&[0, slot_5, 0, slot_6]);
+    }
+    let mut player = PlayerBuilder::from_bytes(mdat, smpl)
+        .starting_subsong(4)
+        .build()
+        .unwrap();
+    let mut kinds = vec![SubsongKind::Valid; 5];
+    kinds.extend([SubsongKind::Duplicate(0)]);
+    kinds.resize(32, SubsongKind::Empty);
+    assert_eq!(player.subsongs(), kinds);
+    assert_eq!(player.module_info().subsongs.len(), 5);
+    // Next and Prev skip the slots that aren't real subsongs
+    player.handle_cmd(PlayerCmd::Prev);
+    assert_eq!(player.current_song_index(), 3);
+    player.handle_cmd(PlayerCmd::Next);
+    player.handle_cmd(PlayerCmd::Next);
+    assert_eq!(player.current_song_index(), 32);
+    assert!(player.song_finished());
+}
+
+/// `mdat` and `smpl` in a single file, behind a TFHD header with type byte `type_`
+fn single_file(mdat: &[u8], smpl: &[u8], type_: u8) -> Vec<u8> {
+    let mut file = b"TFHD".to_vec();
+    file.extend(20_u32.to_be_bytes());
+    file.extend([type_, 0]);
+    file.extend((mdat.len() as u32).to_be_bytes());
+    file.extend((smpl.len() as u32).to_be_bytes());
+    file.extend([0, 0]);
+    file.extend(mdat);
+    file.extend(smpl);
+    file
+}
+
+#[test]
+fn single_file_plays_like_the_pair() {
+    let (mdat, smpl) = synthetic_module();
+    let file = single_file(&mdat, &smpl, 2);
+    let mut player = PlayerBuilder::from_single_file(std::io::Cursor::new(file))
+        .build()
+        .unwrap();
+    assert_eq!(player.module_info().variant, FormatVariant::TfmxPro);
+    for (idx, frames, expected) in GOLDEN {
+        let out = render_song(&mut player, idx);
+        assert_eq!(out.len() / 2, frames, "length of song {idx}");
+        assert_eq!(hash(&out), expected, "output of song {idx}");
+    }
+    // A 7 voice module mixes voices 4 to 7 from the start, so an effect on voice 3
+    // isn't heard
+    let sfx_on_voice_3 = |type_| {
+        let file = single_file(&mdat, &smpl, type_);
+        let mut player = PlayerBuilder::from_single_file(std::io::Cursor::new(file))
+            .build()
+            .unwrap();
+        player.select_song(1);
+        player.trigger_sfx(3, 24, 0, 15, 0).unwrap();
+        let out = render_rest(&mut player);
+        (player.module_info().variant, hash(&out))
+    };
+    assert_ne!(sfx_on_voice_3(2), (FormatVariant::TfmxPro, GOLDEN[1].2));
+    assert_eq!(sfx_on_voice_3(3), (FormatVariant::Tfmx7V, GOLDEN[1].2));
+}
+
+#[test]
+fn builds_from_borrowed_readers() {
+    let (mdat, smpl) = synthetic_module();
+    let mut builder = PlayerBuilder::from_readers(&mdat[..], &smpl[..]);
+    // Every build gets the same data
+    for _ in 0..2 {
+        let mut player = builder.build().unwrap();
+        assert_eq!(hash(&render_song(&mut player, 0)), GOLDEN[0].2);
+    }
+    struct Broken;
+    impl std::io::Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken reader"))
+        }
+    }
+    let mut builder = PlayerBuilder::from_readers(&mdat[..], Broken);
+    let mut read_error = || match builder.build() {
+        Err(PlayerBuildError::Read(e)) => e,
+        _ => panic!("expected a read error"),
+    };
+    let error = read_error();
+    assert_eq!(error.to_string(), "broken reader");
+    assert!(Arc::ptr_eq(&error, &read_error()));
+    assert!(matches!(
+        PlayerBuilder::from_single_file(&mdat[..]).build(),
+        Err(PlayerBuildError::NoSmplSource)
+    ));
+}
+
+#[test]
+fn explicit_linear_is_default() {
+    let mut player = player(|b| {
+        b.interpolation(Interpolation::Linear);
+    });
+    for (idx, _, expected) in GOLDEN {
+        assert_eq!(hash(&render_song(&mut player, idx)), expected);
+    }
+}
+
+#[test]
+fn interpolation_modes_keep_timing() {
+    let mut linear = player(|_| {});
+    let linear_out = render_song::<i16>(&mut linear, 1);
+    for mode in [
+        Interpolation::Nearest,
+        Interpolation::Cubic,
+        Interpolation::Sinc,
+    ] {
+        let mut player = player(|b| {
+            b.interpolation(mode);
+        });
+        let out = render_song::<i16>(&mut player, 1);
+        assert_eq!(out.len(), linear_out.len(), "{mode:?}");
+        assert_ne!(out, linear_out, "{mode:?}");
+        assert!(out.iter().any(|&s| s != 0), "{mode:?}");
+    }
+}
+
+#[test]
+fn limiter_stops_clipping() {
+    let mut loud = player(|b| {
+        b.master_gain(8.0);
+    });
+    let mut limited = player(|b| {
+        b.master_gain(8.0).limiter(true);
+    });
+    for (idx, frames, _) in GOLDEN {
+        render_song::<i16>(&mut loud, idx);
+        assert!(loud.clipped_samples() > 0, "song {idx}");
+        let limited_out = render_song::<i16>(&mut limited, idx);
+        assert_eq!(limited_out.len() / 2, frames, "length of song {idx}");
+        assert_eq!(limited.clipped_samples(), 0, "song {idx}");
+        assert!(limited_out.iter().all(|s| s.unsigned_abs() <= 32_000));
+    }
+}
+
+#[test]
+fn limiter_keeps_events_in_line() {
+    let render = |limiter| {
+        let mut player = player(|b| {
+            b.limiter(limiter);
+        });
+        player.select_song(0);
+        // Render in small pieces, so frames are held back between the calls
+        let mut out = Vec::new();
+        let mut buf = [0i16; 256];
+        let mut events = Vec::new();
+        while out.len() < 8192 {
+            let written = player.render(&mut buf);
+            out.extend_from_slice(&buf[..written]);
+            events.extend(player.drain_events());
+        }
+        let first_sound = out.chunks(2).position(|f| f != [0, 0]);
+        (first_sound, events)
+    };
+    // The audio starts at the same frame, so the events still line up with it
+    let (first_sound, events) = render(true);
+    assert!(first_sound.is_some());
+    assert_eq!((first_sound, events), render(false));
+}
+
+#[test]
+fn output_formats_match() {
+    let interleaved: Vec<i16> = render_song(&mut player(|_| {}), 1);
+    let frames: Vec<[i16; 2]> = interleaved.chunks(2).map(|f| [f[0], f[1]]).collect();
+
+    let floats: Vec<f32> = render_song(&mut player(|_| {}), 1);
+    let expected: Vec<f32> = interleaved
+        .iter()
+        .map(|&s| f32::from(s) / 32_768.0)
+        .collect();
+    assert_eq!(floats, expected);
+
+    let wide: Vec<i32> = render_song(&mut player(|_| {}), 1);
+    let expected: Vec<i32> = interleaved.iter().map(|&s| i32::from(s) << 16).collect();
+    assert_eq!(wide, expected);
+
+    let mut planar_player = player(|b| {
+        b.channel_layout(ChannelLayout::Planar);
+    });
+    // Each call to render lays out the frames it writes, and render_rest makes 4096 sample calls
+    let planar: Vec<i16> = render_song(&mut planar_player, 1);
+    let mut pos = 0;
+    for chunk in planar.chunks(4096) {
+        let (first, second) = chunk.split_at(chunk.len() / 2);
+        for (i, (&a, &b)) in first.iter().zip(second).enumerate() {
+            assert_eq!([a, b], frames[pos + i]);
+        }
+        pos += chunk.len() / 2;
+    }
+    assert_eq!(pos, frames.len());
+
+    let mono: Vec<i16> = render_song(
+        &mut player(|b| {
+            b.channel_layout(ChannelLayout::Mono);
+        }),
+        1,
+    );
+    let expected: Vec<i16> = frames
+        .iter()
+        .map(|&[a, b]| i16::try_from((i32::from(a) + i32::from(b)) >> 1).unwrap())
+        .collect();
+    assert_eq!(mono, expected);
+}
+
+#[test]
+fn estimated_durations_match_the_render() {
+    let mut player = player(|_| {});
+    let frames_to_duration = |frames| Duration::from_nanos(frames * 1_000_000_000 / 44_100);
+    for (idx, frames, _) in GOLDEN {
+        let duration = player.estimate_duration(idx);
+        let out = render_song::<i16>(&mut player, idx);
+        assert_eq!(out.len() / 2, frames);
+        assert_eq!(
+            duration.length,
+            frames_to_duration(frames as u64),
+            "length of song {idx}"
+        );
+        assert_eq!(
+            duration.loop_start,
+            player.loop_point(),
+            "loop of song {idx}"
+        );
+    }
+    assert_eq!(player.estimate_duration(2).loop_start, None);
+    assert_eq!(
+        player.estimate_duration(3).loop_start,
+        Some(frames_to_duration(119_950))
+    );
+}
+
+#[test]
+fn seeking_continues_like_a_full_render() {
+    let mut player = player(|_| {});
+    for (idx, frames, _) in GOLDEN {
+        let full = render_song::<i16>(&mut player, idx);
+        for secs in [0.25, 0.5, 1.25] {
+            let pos = Duration::from_secs_f64(secs);
+            let skipped = (secs * 44_100.0) as usize;
+            player.select_song(idx);
+            player.seek(pos);
+            assert_eq!(player.position(), pos);
+            let tail: Vec<i16> = render_rest(&mut player);
+            assert_eq!(
+                tail.len() / 2,
+                frames - skipped,
+                "length of song {idx} at {secs}s"
+            );
+            assert!(
+                tail == full[skipped * 2..],
+                "output of song {idx} at {secs}s"
+            );
+        }
+    }
+    // Seeking past the end leaves the song finished
+    player.select_song(2);
+    player.seek(Duration::from_secs(10));
+    assert!(player.song_finished());
+    assert_eq!(player.render(&mut [0i16; 64]), 0);
+}
+
+#[test]
+fn songs_loop_the_requested_times() {
+    let render_passes = |passes| {
+        let mut player = player(|builder| {
+            builder.loop_count(passes);
+        });
+        let out = render_song::<i16>(&mut player, 3);
+        assert_eq!(player.completed_loops(), passes);
+        (out, player.loop_point().unwrap())
+    };
+    let (once, loop_point) = render_passes(1);
+    assert_eq!(once.len() / 2, GOLDEN[3].1);
+    assert_eq!(loop_point, Duration::from_nanos(2_719_954_648));
+    // Every pass after the first plays the song from the loop point to where the first
+    // one ended, without the tick that starts the next pass
+    let pass_len = once.len() / 2 - 119_950;
+    for passes in [2, 3] {
+        let (out, point) = render_passes(passes);
+        assert_eq!(point, loop_point);
+        let extra = out.len() / 2 - once.len() / 2;
+        assert!(extra.abs_diff(pass_len * (passes as usize - 1)) <= 1);
+        assert_eq!(out[..once.len()], once);
+    }
+}
+
+/// The chunks of a RIFF/WAVE file, after checking the RIFF header
+fn wav_chunks(wav: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(
+        u32::from_le_bytes(wav[4..8].try_into().unwrap()) as usize,
+        wav.len() - 8
+    );
+    assert_eq!(&wav[8..12], b"WAVE");
+    let mut chunks = Vec::new();
+    let mut rest = &wav[12..];
+    while !rest.is_empty() {
+        let size = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        chunks.push((rest[..4].try_into().unwrap(), &rest[8..8 + size]));
+        rest = &rest[8 + size..];
+    }
+    chunks
+}
+
+#[test]
+fn wav_export_matches_the_render() {
+    let player = player(|_| {});
+    let write = |idx, options: &WavOptions| {
+        let mut out = std::io::Cursor::new(Vec::new());
+        player.write_wav(idx, options, &mut out).unwrap();
+        out.into_inner()
+    };
+    let samples = |data: &[u8]| -> Vec<i16> {
+        data.chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    };
+    for (idx, frames, expected) in GOLDEN {
+        let wav = write(idx, &WavOptions::new());
+        let chunks = wav_chunks(&wav);
+        let ids: Vec<_> = chunks.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [b"fmt ", b"LIST", b"data"]);
+        // PCM, 2 channels, 44.1 kHz, 4 bytes per frame, 16 bits
+        let mut fmt = vec![1, 0, 2, 0];
+        fmt.extend(44_100_u32.to_le_bytes());
+        fmt.extend((44_100_u32 * 4).to_le_bytes());
+        fmt.extend([4, 0, 16, 0]);
+        assert_eq!(chunks[0].1, fmt);
+        let info = chunks[1].1;
+        assert_eq!(&info[..8], b"INFOICMT");
+        let comment = String::from_utf8_lossy(&info[12..]);
+        assert!(comment.starts_with("Synthetic test module\nby the test generator"));
+        let data = chunks[2].1;
+        assert_eq!(data.len(), frames * 4, "size of song {idx}");
+        assert_eq!(hash(&samples(data)), expected, "output of song {idx}");
+    }
+
+    // Songs that loop fade out after the last pass, songs that end don't
+    let mut options = WavOptions::new();
+    options.fade_out(Duration::from_secs(1));
+    let wav = write(3, &options);
+    let data = samples(wav_chunks(&wav)[2].1);
+    assert_eq!(data.len() / 2, GOLDEN[3].1 + 44_100);
+    assert!(data[data.len() - 2..].iter().all(|s| s.abs() < 4));
+    let wav = write(2, &options);
+    assert_eq!(wav_chunks(&wav)[2].1.len(), GOLDEN[2].1 * 4);
+    let wav = write(3, WavOptions::new().loop_count(2));
+    let two_passes = wav_chunks(&wav)[2].1.len() / 4;
+    assert!(two_passes > GOLDEN[3].1 && two_passes < GOLDEN[3].1 * 2);
+    let wav = write(0, WavOptions::new().max_length(Duration::from_secs(1)));
+    assert_eq!(wav_chunks(&wav)[2].1.len(), 44_100 * 4);
+}
+
+#[test]
+fn stems_add_up_to_the_mix() {
+    let mut player = player(|builder| {
+        builder.stereo_separation(100);
+    });
+    for (idx, frames, _) in GOLDEN {
+        let stems = player.render_stems(idx, &WavOptions::new());
+        assert!(stems.iter().all(|stem| stem.len() == frames), "song {idx}");
+        let mix = render_song::<i16>(&mut player, idx);
+        // Voices 1 and 2 are on the right, the others on the left
+        for (i, frame) in mix.chunks_exact(2).enumerate() {
+            let sum = |voices: &[usize]| voices.iter().map(|&v| stems[v][i]).sum::<i16>();
+            assert_eq!(
+                frame,
+                [sum(&[0, 3, 4, 5, 6, 7]), sum(&[1, 2])],
+                "song {idx}"
+            );
+        }
+    }
+    // Song 0 plays voices 0 and 1
+    let stems = player.render_stems(0, &WavOptions::new());
+    assert!(stems[2..].iter().flatten().all(|&s| s == 0));
+    assert!(stems[..2].iter().all(|stem| stem.iter().any(|&s| s != 0)));
+}
+
+#[test]
+fn amiga_filters_smooth_the_output() {
+    let render = |model, led| {
+        let mut player = player(|builder| {
+            builder.amiga_model(model).led_filter(led);
+        });
+        render_song::<i16>(&mut player, 0)
+    };
+    // How much high frequency content there is in the first channel
+    let roughness = |out: &[i16]| -> u64 {
+        out.chunks_exact(2)
+            .map(|f| i64::from(f[0]))
+            .collect::<Vec<_>>()
+            .windows(3)
+            .map(|w| (w[0] - 2 * w[1] + w[2]).unsigned_abs())
+            .sum()
+    };
+    let unfiltered = render(AmigaModel::Unfiltered, false);
+    assert_eq!(hash(&unfiltered), GOLDEN[0].2);
+    // The LED filter works on its own too
+    assert!(roughness(&render(AmigaModel::Unfiltered, true)) < roughness(&unfiltered));
+    let a1200 = render(AmigaModel::A1200, false);
+    let a500 = render(AmigaModel::A500, false);
+    let a500_led = render(AmigaModel::A500, true);
+    for out in [&a1200, &a500, &a500_led] {
+        assert_eq!(out.len(), unfiltered.len());
+    }
+    assert!(roughness(&a1200) < roughness(&unfiltered));
+    assert!(roughness(&a500) < roughness(&a1200));
+    assert!(roughness(&a500_led) < roughness(&a500));
+    // The LED filter can be switched while playing
+    let mut player = player(|builder| {
+        builder.amiga_model(AmigaModel::A500);
+    });
+    player.select_song(0);
+    player.handle_cmd(PlayerCmd::ToggleLedFilter);
+    assert!(render_rest::<i16>(&mut player) == a500_led);
+}
+
+#[test]
+fn pans_and_separation_place_the_voices() {
+    let render = |configure: &dyn Fn(&mut PlayerBuilder)| {
+        let mut player = player(configure);
+        render_song::<i16>(&mut player, 0)
+    };
+    let frames =
+        |out: &[i16]| -> Vec<[i16; 2]> { out.chunks_exact(2).map(|f| [f[0], f[1]]).collect() };
+    // The blend is the default preset
+    let blend = render(&|builder| {
+        builder
+            .stereo_separation(PlayerBuilder::BLEND_SEPARATION)
+            .voice_pans(PlayerBuilder::DEFAULT_PANS);
+    });
+    assert_eq!(hash(&blend), GOLDEN[0].2);
+    let hard = render(&|builder| {
+        builder.stereo_separation(100);
+    });
+    assert_ne!(hard, blend);
+    // Song 0 plays voices 0 and 1, which are on opposite sides
+    assert!(frames(&hard).iter().any(|&[l, r]| l != 0 && r == 0));
+    assert!(frames(&hard).iter().any(|&[l, r]| l == 0 && r != 0));
+    let mono = render(&|builder| {
+        builder.stereo_separation(0);
+    });
+    assert!(frames(&mono).iter().all(|&[l, r]| l == r));
+    assert!(mono.iter().any(|&s| s != 0));
+    let centered = render(&|builder| {
+        builder.stereo_separation(100).voice_pans([0; 8]);
+    });
+    assert!(frames(&centered).iter().all(|&[l, r]| l == r));
+    let all_left = render(&|builder| {
+        builder.stereo_separation(100).voice_pans([-100; 8]);
+    });
+    assert!(frames(&all_left).iter().all(|&[_, r]| r == 0));
+    assert!(all_left.iter().any(|&s| s != 0));
+    // The same, changed while playing
+    let mut player = player(|_| {});
+    player.select_song(0);
+    player.handle_cmd(PlayerCmd::SetSeparation(100));
+    for voice in 0..8 {
+        player.handle_cmd(PlayerCmd::SetPan(voice, -100));
+    }
+    assert!(render_rest::<i16>(&mut player) == all_left);
+}
+
+#[test]
+fn wav_channels_are_left_first() {
+    // Energy of the left and the right channel of a WAV file with only voice 0 playing
+    let energies = |pan| {
+        let mut pans = [0; 8];
+        pans[0] = pan;
+        let mut player = player(|builder| {
+            builder.stereo_separation(100).voice_pans(pans);
+        });
+        player.handle_cmd(PlayerCmd::ToggleCh(1));
+        let mut out = std::io::Cursor::new(Vec::new());
+        player.write_wav(0, &WavOptions::new(), &mut out).unwrap();
+        let wav = out.into_inner();
+        let data = wav_chunks(&wav)[2].1;
+        let mut energies = [0_u64; 2];
+        for (i, sample) in data.chunks_exact(2).enumerate() {
+            let sample = i64::from(i16::from_le_bytes([sample[0], sample[1]]));
+            energies[i % 2] += (sample * sample) as u64;
+        }
+        energies
+    };
+    let [left, right] = energies(-100);
+    assert!(left > 0);
+    assert_eq!(right, 0);
+    let [left, right] = energies(100);
+    assert_eq!(left, 0);
+    assert!(right > 0);
+}
+
+#[test]
+fn sfx_layer_over_music() {
+    let mut player = player(|_| {});
+    player.select_song(1);
+    assert_eq!(
+        player.trigger_sfx(4, 24, 0, 15, 0),
+        Err(SfxError::NoSuchChannel(4))
+    );
+    assert_eq!(
+        player.trigger_sfx(2, 64, 0, 15, 0),
+        Err(SfxError::InvalidNote(64))
+    );
+    assert_eq!(
+        player.trigger_sfx(2, 24, 3, 15, 0),
+        Err(SfxError::NoSuchMacro(3))
+    );
+    assert_eq!(
+        player.trigger_sfx(2, 24, 0, 16, 0),
+        Err(SfxError::InvalidVolume(16))
+    );
+    player.lock_channel(2, 5, 100).unwrap();
+    assert_eq!(player.trigger_sfx(2, 24, 0, 15, 4), Err(SfxError::Locked));
+    player.trigger_sfx(2, 24, 0, 15, 5).unwrap();
+    let out = render_rest(&mut player);
+    assert_eq!(out.len() / 2, GOLDEN[1].1);
+    assert_ne!(hash(&out), GOLDEN[1].2);
+    assert_eq!(
+        player.trigger_sfx(2, 24, 0, 15, 0),
+        Err(SfxError::NotPlaying)
+    );
+    // Note 0 with macro 0 at volume 0 on channel 0 is a note too
+    player.select_song(2);
+    player.drain_events().for_each(drop);
+    player.trigger_sfx(0, 0, 0, 0, 0).unwrap();
+    player.render(&mut [0i16; 64]);
+    assert!(player.drain_events().any(|event| matches!(
+        event.kind,
+        EventKind::NoteOn {
+            channel: 0,
+            note: 0,
+            macro_num: 0,
+            velocity: 0,
+            ..
+        }
+    )));
+    // An effect waiting for its tick keeps out effects with a lower priority
+    player.select_song(2);
+    player.drain_events().for_each(drop);
+    player.trigger_sfx(0, 5, 0, 15, 4).unwrap();
+    assert_eq!(player.trigger_sfx(0, 7, 0, 15, 3), Err(SfxError::Locked));
+    player.trigger_sfx(0, 9, 0, 15, 4).unwrap();
+    player.render(&mut [0i16; 64]);
+    let notes: Vec<_> = player
+        .drain_events()
+        .filter_map(|event| match event.kind {
+            EventKind::NoteOn {
+                channel: 0, note, ..
+            } => Some(note),
+            _ => None,
+        })
+        .collect();
+    assert!(notes.contains(&9));
+    assert!(!notes.contains(&5) && !notes.contains(&7));
+}
+
+#[test]
+fn cue_changes_are_reported() {
+    let mut player = player(|_| {});
+    player.select_song(0);
+    assert_eq!(player.cues(), [0; 4]);
+    let out = render_song::<i16>(&mut player, 0);
+    let events: Vec<_> = player.drain_events().collect();
+    let cue = |index| {
+        events
+            .iter()
+            .find(|e| matches!(e.kind, EventKind::CueChanged { index: i, .. } if i == index))
+            .copied()
+            .unwrap()
+    };
+    assert_eq!(
+        cue(0).kind,
+        EventKind::CueChanged {
+            index: 0,
+            value: 0x1234
+        }
+    );
+    assert_eq!(
+        cue(1).kind,
+        EventKind::CueChanged {
+            index: 1,
+            value: 0x0abc
+        }
+    );
+    assert!(events.windows(2).all(|w| w[0].frame <= w[1].frame));
+    assert!(events.iter().all(|e| e.frame < (out.len() / 2) as u64));
+    assert_eq!(player.cues(), [0x1234, 0x0abc, 0, 0]);
+    assert_eq!(player.drain_events().count(), 0);
+}
+
+#[test]
+fn sequencer_events_are_reported() {
+    let mut player = player(|_| {});
+    render_song::<i16>(&mut player, 0);
+    let events: Vec<_> = player.drain_events().collect();
+    let at_start: Vec<_> = events
+        .iter()
+        .filter(|e| e.frame == 0)
+        .map(|e| e.kind)
+        .collect();
+    assert!(at_start.contains(&EventKind::TrackStep { position: 0 }));
+    assert!(at_start.contains(&EventKind::PatternChange {
+        voice: 1,
+        pattern: 1,
+        transpose: 0
+    }));
+    assert!(at_start.contains(&EventKind::NoteOn {
+        channel: 0,
+        note: 24,
+        macro_num: 0,
+        velocity: 15,
+        fine_tune: 0
+    }));
+    assert!(
+        events
+            .iter()
+            .any(|e| e.kind == EventKind::TrackStep { position: 1 } && e.frame > 0)
+    );
+    let notes = |transpose: u8| {
+        events
+            .iter()
+            .filter(|e| matches!(e.kind, EventKind::NoteOn { channel: 0, note, .. } if note == 24 + transpose))
+            .count()
+    };
+    assert_eq!(notes(0), 1);
+    assert_eq!(notes(12), 1);
+    assert!(
+        events
+            .iter()
+            .any(|e| e.kind == EventKind::KeyUp { channel: 0 })
+    );
+}
+
+#[test]
+fn tempo_steps_set_the_timer() {
+    let (mdat, smpl) = synthetic_module();
+    // Song 1 changes the tempo at track step 3
+    let play_with_tempo = |tempo| {
+        let mut module = Module::from_mdat(&mdat).unwrap();
+        module.tracks[3] = [0xEFFE, 2, 3, tempo, 0, 0, 0, 0];
+        let mut player = PlayerBuilder::from_module(&module, smpl.clone())
+            .build()
+            .unwrap();
+        let frames = render_song::<i16>(&mut player, 1).len() / 2;
+        let e_clocks = player.drain_events().find_map(|e| match e.kind {
+            EventKind::TempoChange { speed: 3, e_clocks } => Some(e_clocks),
+            _ => None,
+        });
+        (TrackCommand::decode(module.tracks[3]), e_clocks, frames)
+    };
+    let (command, e_clocks, frames) = play_with_tempo(250);
+    assert_eq!(
+        command,
+        TrackCommand::Tempo {
+            speed: 3,
+            bpm: Some(250)
+        }
+    );
+    assert_eq!(e_clocks, Some(0x001b_51f8 / 250));
+    assert!(frames < GOLDEN[1].1);
+    // Tempos up to 15, and ones with any of the bits 0xF200 set, leave the timer alone
+    for tempo in [0, 15, 0x8000 | 250] {
+        let (command, e_clocks, frames) = play_with_tempo(tempo);
+        assert_eq!(
+            command,
+            TrackCommand::Tempo {
+                speed: 3,
+                bpm: None
+            }
+        );
+        assert_eq!(e_clocks, Some(14318));
+        assert_eq!(frames, GOLDEN[1].1);
+    }
+}
+
+#[test]
+fn voice_meters_follow_the_mix() {
+    assert!(player(|_| {}).voice_meters().is_none());
+    let mut player = player(|b| {
+        b.voice_meters(true).scope_decimation(16);
+    });
+    player.select_song(1);
+    let mut buf = [0i16; 4096];
+    assert_eq!(player.render(&mut buf), buf.len());
+    assert_eq!(
+        hash(&buf),
+        hash(&render_song::<i16>(&mut self::player(|_| {}), 1)[..4096])
+    );
+    let meters = player.voice_meters().unwrap();
+    assert!(meters[1].peak > 0.0 && meters[1].peak <= 1.0);
+    assert!(meters[1].rms > 0.0 && meters[1].rms <= meters[1].peak);
+    assert!(meters[1].period != 0);
+    assert_eq!(meters[1].scope.len(), 2048 / 16);
+    assert_eq!(meters[5].peak, 0.0);
+    assert!(meters[5].scope.iter().all(|&s| s == 0.0));
+}
+
+#[test]
+fn midi_export_has_a_track_per_channel() {
+    let player = player(|_| {});
+    let mut smf = Vec::new();
+    player.write_midi(0, &mut smf).unwrap();
+    assert_eq!(&smf[..8], b"MThd\0\0\0\x06");
+    // Format 1, with the conductor track and channels 0 and 1
+    assert_eq!(&smf[8..12], &[0, 1, 0, 3]);
+    let mut tracks = Vec::new();
+    let mut rest = &smf[14..];
+    while !rest.is_empty() {
+        assert_eq!(&rest[..4], b"MTrk");
+        let len = u32::from_be_bytes(rest[4..8].try_into().unwrap()) as usize;
+        tracks.push(&rest[8..8 + len]);
+        rest = &rest[8 + len..];
+    }
+    assert_eq!(tracks.len(), 3);
+    for track in &tracks {
+        assert!(track.ends_with(&[0xFF, 0x2F, 0]));
+    }
+    // Set tempo on the conductor track, and the first note at middle C
+    assert!(tracks[0].windows(3).any(|w| w == [0xFF, 0x51, 3]));
+    assert!(tracks[1].windows(3).any(|w| w == [0x90, 60, 127]));
+    assert!(tracks[1].windows(2).any(|w| w == [0x80, 60]));
+}
+
+#[test]
+fn instruments_are_found_in_macros() {
+    let player = player(|_| {});
+    let instruments = player.instruments();
+    let instrument = |range, loop_range, macro_num| Instrument {
+        range,
+        loop_range: Some(loop_range),
+        macros: vec![macro_num],
+    };
+    assert_eq!(
+        instruments,
+        [
+            instrument(0..32, 0..32, 0),
+            instrument(0..32, 8..32, 2),
+            instrument(32..96, 32..96, 1),
+        ]
+    );
+    let (_, smpl) = synthetic_module();
+
+    let mut svx = Vec::new();
+    player
+        .write_instrument(&instruments[1], InstrumentFormat::Iff8svx, &mut svx)
+        .unwrap();
+    assert_eq!(&svx[..4], b"FORM");
+    assert_eq!(&svx[8..16], b"8SVXVHDR");
+    // One shot and repeat lengths
+    assert_eq!(&svx[20..28], &[0, 0, 0, 8, 0, 0, 0, 24]);
+    assert!(svx.ends_with(&[b"BODY".as_slice(), &[0, 0, 0, 32], &smpl[..32]].concat()));
+
+    let mut wav = Vec::new();
+    player
+        .write_instrument(&instruments[1], InstrumentFormat::Wav, &mut wav)
+        .unwrap();
+    assert_eq!(&wav[8..12], b"WAVE");
+    let smpl_chunk = wav.windows(4).position(|w| w == b"smpl").unwrap();
+    let loop_points = &wav[smpl_chunk + 8 + 44..smpl_chunk + 8 + 52];
+    assert_eq!(loop_points, &[8, 0, 0, 0, 31, 0, 0, 0]);
+    let data: Vec<u8> = smpl[..32].iter().map(|s| s ^ 0x80).collect();
+    assert!(wav.ends_with(&data));
+}
+
+#[test]
+fn macros_disassemble() {
+    let player = player(|_| {});
+    let commands: Vec<_> = player
+        .disassemble_macro(2)
+        .unwrap()
+        .into_iter()
+        .map(|step| step.command)
+        .collect();
+    assert_eq!(
+        commands,
+        [
+            MacroCommand::DmaOff {
+                reset: true,
+                after_loop: false,
+                volume: 0,
+                absolute: false
+            },
+            MacroCommand::SetBegin { addr: 0 },
+            MacroCommand::SetLen { len: 16 },
+            MacroCommand::SetNote {
+                note: 12,
+                detune: 0
+            },
+            MacroCommand::SetVolume {
+                volume: 0x38,
+                skip: false
+            },
+            MacroCommand::DmaOn { effects: 0 },
+            MacroCommand::SampleLoop { offset: 8 },
+            MacroCommand::Wait {
+                ticks: 4,
+                once: false
+            },
+            MacroCommand::WaitKeyUp { ticks: 3 },
+            MacroCommand::Portamento { speed: 1, rate: 8 },
+            MacroCommand::Wait {
+                ticks: 20,
+                once: false
+            },
+            MacroCommand::Stop,
+        ]
+    );
+    let cue = player.disassemble_macro(1).unwrap()[7];
+    assert_eq!(cue.to_string(), "0007: 20010ABC  Cue index=1 value=$0ABC");
+    assert!(player.disassemble_macro(3).is_none());
+
+    // Operand bytes the player reads show up, so different steps don't look the same
+    let shown = |bytes| MacroCommand::decode(bytes).to_string();
+    assert_eq!(shown([0, 0, 0, 0]), "DMAoff+Reset");
+    assert_eq!(shown([0, 1, 0, 0]), "DMAoff+Reset after_loop");
+    assert_eq!(shown([0, 0, 1, 0x20]), "DMAoff+Reset volume=32 absolute");
+    assert_eq!(shown([19, 1, 0, 0]), "DMAoff after_loop");
+    assert_eq!(shown([4, 1, 0, 2]), "Wait ticks=2 once");
+    assert_eq!(shown([14, 0, 0xFE, 0x30]), "SetVolume volume=48 skip");
+    for bytes in [
+        [0, 1, 1, 0x20],
+        [4, 1, 0, 2],
+        [13, 0, 0xFE, 3],
+        [19, 1, 0, 0],
+    ] {
+        assert_eq!(MacroCommand::decode(bytes).encode(), bytes);
+    }
+    // Encoding drops the bits the player ignores, and nothing else
+    for opcode in 0..=0xFF {
+        for operand in OPERANDS {
+            let command = MacroCommand::decode([opcode, operand[0], operand[1], operand[2]]);
+            assert_eq!(MacroCommand::decode(command.encode()), command);
+        }
+    }
+    assert_eq!(MacroCommand::decode([0, 7, 0, 0]).encode(), [0, 1, 0, 0]);
+
+    let unknown = MacroCommand::decode([27, 1, 2, 3]);
+    assert!(unknown.is_unknown());
+    assert_eq!(unknown.to_string(), "Unknown $1B 010203");
+}
+
+#[test]
+fn patterns_and_tracks_disassemble() {
+    let player = player(|_| {});
+    let pattern: Vec<_> = player
+        .disassemble_pattern(1)
+        .unwrap()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        pattern,
+        [
+            "0000: 8C01C108  C-1 01 C1 wait=8",
+            "0001: FD001234  Cue index=0 value=$1234",
+            "0002: 9301C108  G-1 01 C1 wait=8",
+            "0003: F0000000  End",
+        ]
+    );
+    assert_eq!(
+        player.disassemble_pattern(2).unwrap()[1].command,
+        PatternCommand::Portamento {
+            note: 40,
+            speed: 4,
+            channel: 2,
+            rate: 0x20
+        }
+    );
+    assert!(player.disassemble_pattern(3).is_none());
+
+    let song = player.disassemble_track(1).unwrap();
+    assert_eq!(song.len(), 3);
+    assert_eq!(
+        song[1].command,
+        TrackCommand::Tempo {
+            speed: 3,
+            bpm: None
+        }
+    );
+    assert_eq!(
+        song[2].to_string(),
+        "0004: 02+05 | 80+00 | -- -- | -- -- | -- -- | -- -- | -- -- | -- --"
+    );
+    let song = player.disassemble_track(3).unwrap();
+    assert_eq!(
+        song[2].command,
+        TrackCommand::Loop {
+            position: 8,
+            count: 1
+        }
+    );
+
+    // Encoding drops the bits the player ignores, and nothing else
+    for first in 0..=0xFF {
+        for operand in OPERANDS {
+            let command = PatternCommand::decode([first, operand[0], operand[1], operand[2]]);
+            assert_eq!(PatternCommand::decode(command.encode()), command);
+        }
+    }
+    assert_eq!(
+        PatternCommand::decode([0x4C, 1, 0xC1, 0]).encode(),
+        [0x0C, 1, 0xC1, 0]
+    );
+}
+
+#[test]
+fn module_round_trips() {
+    let (mdat, smpl) = synthetic_module();
+    let module = Module::from_mdat(&mdat).unwrap();
+    assert_eq!(module.to_mdat(), mdat);
+    // Unused header bytes are kept, but don't count for equality
+    let mut padded_mdat = mdat.clone();
+    padded_mdat[0x1FC..0x200].fill(0xAA);
+    let padded = Module::from_mdat(&padded_mdat).unwrap();
+    assert_eq!(padded.to_mdat(), padded_mdat);
+    assert_eq!(padded, module);
+    assert_eq!(module.patterns.len(), 3);
+    assert_eq!(module.macros.len(), 3);
+    assert_eq!(module.songs[1].first_step, 2);
+    assert_eq!(
+        module.track_commands().nth(3),
+        Some(TrackCommand::Tempo {
+            speed: 3,
+            bpm: None
+        })
+    );
+    assert_eq!(
+        module.patterns[1].commands().nth(1),
+        Some(PatternCommand::Cue {
+            index: 0,
+            value: 0x1234
+        })
+    );
+
+    // A NOP at the start of the first pattern moves everything after it, but plays the same
+    let mut edited = module.clone();
+    edited.patterns[0].steps.insert(0, [0xFF, 0, 0, 0]);
+    let edited_mdat = edited.to_mdat();
+    assert_eq!(edited_mdat.len(), mdat.len() + 4);
+    assert_eq!(Module::from_mdat(&edited_mdat).unwrap(), edited);
+    let mut player = PlayerBuilder::from_module(&edited, smpl).build().unwrap();
+    let (idx, frames, expected) = GOLDEN[0];
+    let out = render_song(&mut player, idx);
+    assert_eq!(out.len() / 2, frames);
+    assert_eq!(hash(&out), expected);
+}
+
+#[test]
+fn module_assembles_from_source() {
+    let (mdat, smpl) = synthetic_module();
+    let mut module = Module::from_mdat(&mdat).unwrap();
+    // Track steps with words that their commands don't show
+    module.tracks.extend([
+        [0xEFFE, 2, 5, 0x8000, 0, 0, 0, 0],
+        [0xEFFE, 4, 0x0102, 0x0140, 0, 7, 0, 0],
+    ]);
+    module.macros[2].steps[7] = [4, 1, 0, 4];
+    let source = module.to_source();
+    assert!(source.contains("\npattern 01\n    0000: 8C01C108  C-1 01 C1 wait=8\n"));
+    assert!(source.contains("\n    000D: EFFE0002 00058000 00000000 00000000  Tempo speed=5\n"));
+    assert!(source.contains("\n    0007: 04010004  Wait ticks=4 once\n"));
+    // The header text keeps its NUL padding
+    assert!(source.starts_with("text \"Synthetic test module\" pad=0\n"));
+    assert!(source.contains("\ntext \"\" pad=0\n"));
+    let assembled = Module::assemble(&source).unwrap();
+    assert_eq!(assembled, module);
+    // Empty sections assemble too
+    let mut empty = module.clone();
+    empty.patterns.push(Pattern::default());
+    empty.macros.push(Macro::default());
+    assert_eq!(Module::assemble(&empty.to_source()).unwrap(), empty);
+    assert_eq!(
+        Module::assemble(&Module::default().to_source()).unwrap(),
+        Module::default()
+    );
+    let mut player = PlayerBuilder::from_module(&assembled, smpl.clone())
+        .build()
+        .unwrap();
+    let (idx, frames, expected) = GOLDEN[0];
+    let out = render_song(&mut player, idx);
+    assert_eq!(out.len() / 2, frames);
+    assert_eq!(hash(&out), expected);
+
+    let source = r#"
+        text "Written by hand"  ; no bytes or step numbers
+        text "  mixed  " pad=$2E
+        song 0 first=0 last=1 tempo=5
+
+        tracks
+            Tempo speed=5 bpm=125
+            00+00 | 01-02
+        pattern 00
+            C-2 00 F0 wait=4
+            Wait ticks=3
+            End
+        pattern 01
+            End
+        macro 00
+            DMAoff+Reset after_loop volume=16
+            DMAon effects=0
+            Wait ticks=10 once
+            SetVolume volume=48 skip
+            Stop
+    "#;
+    let module = Module::assemble(source).unwrap();
+    assert_eq!(module.tracks[0][..4], [0xEFFE, 2, 5, 125]);
+    assert_eq!(module.tracks[1][..3], [0x0000, 0x01FE, 0xFF00]);
+    assert_eq!(
+        module.patterns[0].steps,
+        [[0x98, 0, 0xF0, 4], [0xF3, 3, 0, 0], [0xF0, 0, 0, 0]]
+    );
+    assert_eq!(
+        module.macros[0].steps[..4],
+        [
+            [0, 1, 0, 16],
+            [1, 0, 0, 0],
+            [4, 1, 0, 10],
+            [14, 0, 0xFE, 48]
+        ]
+    );
+    let mut player = PlayerBuilder::from_module(&module, smpl).build().unwrap();
+    assert_eq!(module.text[1][..12], *b"  mixed  ...");
+    assert_eq!(Module::assemble(&module.to_source()).unwrap(), module);
+    assert_eq!(
+        player.module_info().text,
+        [
+            "Written by hand".to_owned(),
+            format!("  mixed  {:.<31}", "")
+        ]
+    );
+    render_song::<i16>(&mut player, 0);
+    assert!(player.drain_events().any(|e| e.kind
+        == EventKind::TempoChange {
+            speed: 5,
+            e_clocks: 0x001b_51f8 / 125
+        }));
+    // Operand bits the player ignores aren't kept by encode
+    for words in [
+        [0xEFFE, 2, 5, 125, 0, 0, 0, 0],
+        [0xEFFE, 2, 5, 0x8000 | 125, 0, 0, 0, 0],
+        [0xEFFE, 2, 5, 0x0100 | 125, 0, 0, 0, 0],
+    ] {
+        let command = TrackCommand::decode(words);
+        assert_eq!(TrackCommand::decode(command.encode()), command);
+    }
+
+    let error = |source| Module::assemble(source).unwrap_err();
+    assert_eq!(
+        error("tracks\n    Stop\npattern 01\n"),
+        AssembleError {
+            line: 3,
+            kind: AssembleErrorKind::OutOfOrder(0)
+        }
+    );
+    assert_eq!(
+        error("tracks\n    05+00\n").kind,
+        AssembleErrorKind::UndefinedPattern(5)
+    );
+    assert_eq!(
+        error("tracks\n    Stop\nmacro 00\n    Jump step=1\n").to_string(),
+        "Line 4: Unknown command `Jump`"
+    );
+}